@@ -0,0 +1,62 @@
+//! Prints a file from a FAT32 disk image, or lists a directory.
+//!
+//! ```text
+//! fat32cat <image> <path>
+//! ```
+//!
+//! `path` is absolute within the image, e.g. `/README.TXT`.
+
+extern crate fat32;
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::process;
+
+use fat32::traits::{Dir, Entry, File, FileSystem};
+use fat32::vfat::{self, Error, Shared, VFat};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <image> <path>", args[0]);
+        process::exit(2);
+    }
+    if let Err(e) = run(&args[1], &args[2]) {
+        eprintln!("fat32cat: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(image: &str, path: &str) -> io::Result<()> {
+    let vfat = mount(image)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match vfat.open(path)? {
+        vfat::Entry::Dir(dir) => for entry in dir.entries()? {
+            if entry.name() == "." || entry.name() == ".." {
+                continue;
+            }
+            match entry.as_file() {
+                Some(file) => writeln!(out, "{:>10}  {}", file.size(), entry.name())?,
+                None => writeln!(out, "{:>10}  {}/", "<DIR>", entry.name())?,
+            }
+        },
+        vfat::Entry::File(mut file) => {
+            io::copy(&mut file, &mut out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Mounts `image`, whether it is partitioned or a bare file system.
+fn mount(image: &str) -> io::Result<Shared<VFat>> {
+    let open = || OpenOptions::new().read(true).open(image);
+    let mounted = match VFat::from(open()?) {
+        Err(Error::Unpartitioned) => VFat::from_unpartitioned(open()?),
+        mounted => mounted,
+    };
+    mounted.map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("can't mount {}: {:?}", image, e))
+    })
+}
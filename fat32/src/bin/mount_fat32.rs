@@ -0,0 +1,43 @@
+//! Mounts a FAT32 disk image read-only through FUSE, serving until it is
+//! unmounted.
+//!
+//! ```text
+//! mount.fat32-rs <image> <mountpoint>
+//! ```
+
+extern crate fat32;
+extern crate fuser;
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io;
+use std::process;
+
+use fat32::fuse::FuseAdapter;
+use fat32::vfat::VFat;
+use fuser::MountOption;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <image> <mountpoint>", args[0]);
+        process::exit(2);
+    }
+    if let Err(e) = run(&args[1], &args[2]) {
+        eprintln!("mount.fat32-rs: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(image: &str, mountpoint: &str) -> io::Result<()> {
+    let device = OpenOptions::new().read(true).open(image)?;
+    let vfat = VFat::from(device).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("can't mount {}: {:?}", image, e))
+    })?;
+    let options = [
+        MountOption::RO,
+        MountOption::FSName(image.to_string()),
+        MountOption::Subtype("fat32-rs".to_string()),
+    ];
+    fuser::mount2(FuseAdapter::new(vfat), mountpoint, &options)
+}
@@ -0,0 +1,264 @@
+use std::cmp::min;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+            ReplyEntry, Request, FUSE_ROOT_ID};
+use libc;
+
+use traits::{Dir, Entry, File, Metadata};
+use vfat::{self, Shared, VFat};
+use vfat::dir::EntrySlot;
+
+/// How long the kernel may cache attributes and lookups. The volume is
+/// mounted read-only, so nothing changes underneath it.
+const TTL: Duration = Duration::from_secs(1);
+
+/// Exposes a `VFat` through the `fuser` crate's `Filesystem` trait.
+///
+/// The adapter is read-only: only `lookup`, `getattr`, `read`, `release` and
+/// `readdir` are implemented, everything else is answered by `fuser`'s
+/// defaults (`ENOSYS`). FAT32 has no inode numbers, so inodes are handed out
+/// on first sight of an entry and remain valid for the life of the adapter.
+/// They are keyed by where the entry's directory slot lies, so an entry
+/// reached under another case of its name or through `.` and `..` keeps its
+/// inode.
+///
+/// ```ignore
+/// let vfat = VFat::from(File::open("image.img")?)?;
+/// fuser::mount2(FuseAdapter::new(vfat), "/mnt", &[MountOption::RO])?;
+/// ```
+pub struct FuseAdapter {
+    vfat: Shared<VFat>,
+    /// `nodes[ino - 1]` is the entry of inode `ino`.
+    nodes: Vec<Node>,
+    inodes: HashMap<EntrySlot, u64>,
+    /// Files being read, by inode, so consecutive reads continue from an
+    /// open file. Dropped when the kernel releases the file.
+    open_files: HashMap<u64, vfat::File>,
+    uid: u32,
+    gid: u32,
+}
+
+/// An entry the kernel has been given an inode for.
+struct Node {
+    entry: vfat::Entry,
+    /// The inode of the directory holding the entry.
+    parent: u64,
+}
+
+impl FuseAdapter {
+    /// Creates an adapter serving `vfat`. Every entry is reported as owned by
+    /// the user running the process.
+    pub fn new(vfat: Shared<VFat>) -> FuseAdapter {
+        let root = vfat::Dir::root_from_vfat(vfat.clone());
+        FuseAdapter {
+            vfat,
+            nodes: vec![Node { entry: vfat::Entry::Dir(root), parent: FUSE_ROOT_ID }],
+            inodes: HashMap::new(),
+            open_files: HashMap::new(),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    /// Returns the node of inode `ino`.
+    fn node(&self, ino: u64) -> io::Result<&Node> {
+        self.nodes
+            .get((ino as usize).wrapping_sub(1))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown inode."))
+    }
+
+    /// Returns the inode of `entry`, found in the directory of inode
+    /// `parent`, assigning a fresh one if the entry hasn't been seen before.
+    fn inode(&mut self, entry: vfat::Entry, parent: u64) -> u64 {
+        let slot = match entry {
+            vfat::Entry::File(ref file) => file.entry_slot(),
+            vfat::Entry::Dir(ref dir) => dir.entry_slot(),
+        };
+        if let Some(&ino) = slot.and_then(|slot| self.inodes.get(&slot)) {
+            return ino;
+        }
+        self.nodes.push(Node { entry, parent });
+        let ino = self.nodes.len() as u64;
+        if let Some(slot) = slot {
+            self.inodes.insert(slot, ino);
+        }
+        ino
+    }
+
+    /// Returns the inode of the entry `name` in the directory of inode
+    /// `parent`, or the `errno` value to answer with.
+    fn child(&mut self, parent: u64, name: &OsStr) -> Result<u64, i32> {
+        let (dir, grandparent) = {
+            let node = self.node(parent).map_err(|e| errno(&e))?;
+            match node.entry.as_dir() {
+                Some(dir) => (dir.clone(), node.parent),
+                None => return Err(libc::ENOTDIR),
+            }
+        };
+        match name.to_str() {
+            Some(".") => Ok(parent),
+            Some("..") => Ok(grandparent),
+            _ => {
+                let entry = dir.find(name).map_err(|e| errno(&e))?;
+                Ok(self.inode(entry, parent))
+            }
+        }
+    }
+
+    fn attr(&self, ino: u64) -> io::Result<FileAttr> {
+        let entry = &self.node(ino)?.entry;
+        let metadata = entry.metadata();
+        let time = |ts: vfat::Timestamp| ts.to_system_time().unwrap_or(UNIX_EPOCH);
+        let (kind, size, perm, nlink) = match entry.as_file() {
+            Some(file) => (FileType::RegularFile, file.size(), 0o444, 1),
+            None => (FileType::Directory, 0, 0o555, 2),
+        };
+        Ok(FileAttr {
+            ino,
+            size,
+            blocks: (size + 511) / 512,
+            atime: time(metadata.accessed()),
+            mtime: time(metadata.modified()),
+            ctime: time(metadata.modified()),
+            crtime: time(metadata.created()),
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: self.vfat.borrow().cluster_size() as u32,
+            flags: 0,
+        })
+    }
+
+    fn read_at(&mut self, ino: u64, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+        if !self.open_files.contains_key(&ino) {
+            let file = self.node(ino)?.entry.as_file().cloned().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Inode is not a file.")
+            })?;
+            self.open_files.insert(ino, file);
+        }
+        let file = self.open_files.get_mut(&ino).expect("file was just opened");
+        let offset = min(offset, file.size());
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut buf = vec![0; size];
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read(&mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        buf.truncate(read);
+        Ok(buf)
+    }
+}
+
+/// Maps an I/O error onto the closest `errno` value.
+fn errno(error: &io::Error) -> i32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => libc::ENOENT,
+        io::ErrorKind::InvalidInput => libc::EINVAL,
+        _ => libc::EIO,
+    }
+}
+
+impl Filesystem for FuseAdapter {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let ino = match self.child(parent, name) {
+            Ok(ino) => ino,
+            Err(errno) => return reply.error(errno),
+        };
+        match self.attr(ino) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        if offset < 0 {
+            return reply.error(libc::EINVAL);
+        }
+        match self.read_at(ino, offset as u64, size as usize) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.open_files.remove(&ino);
+        reply.ok();
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let (dir, parent) = match self.node(ino) {
+            Ok(node) => match node.entry.as_dir() {
+                Some(dir) => (dir.clone(), node.parent),
+                None => return reply.error(libc::ENOTDIR),
+            },
+            Err(e) => return reply.error(errno(&e)),
+        };
+        let entries = match dir.entries() {
+            Ok(entries) => entries.collect::<Vec<_>>(),
+            Err(e) => return reply.error(errno(&e)),
+        };
+
+        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+            let name = entry.name().to_string();
+            let kind = if entry.is_dir() {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            let entry_ino = match name.as_str() {
+                "." => ino,
+                ".." => parent,
+                _ => self.inode(entry, ino),
+            };
+            // `add` returns `true` once the reply buffer is full.
+            if reply.add(entry_ino, (i + 1) as i64, kind, &name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
@@ -4,12 +4,21 @@
 #[cfg(not(target_endian = "little"))]
 compile_error!("only little endian platforms supported");
 
+#[cfg(feature = "fuse")]
+extern crate fuser;
+#[cfg(feature = "fuse")]
+extern crate libc;
+
 #[cfg(test)]
 mod tests;
+#[cfg(test)]
+mod testutil;
 mod mbr;
 mod util;
 
 pub mod vfat;
 pub mod traits;
+#[cfg(feature = "fuse")]
+pub mod fuse;
 
 pub use mbr::*;
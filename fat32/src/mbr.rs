@@ -9,6 +9,14 @@ pub struct CHS {
     sector_and_cylinder: u16, // sector (Bits 6-7 are the upper two bits for the Starting Cylinder field.) and Cylinder
 }
 
+impl CHS {
+    /// The 3 bytes of the address, as stored in a partition entry.
+    fn as_bytes(&self) -> [u8; 3] {
+        let sector_and_cylinder = self.sector_and_cylinder;
+        [self.head, sector_and_cylinder as u8, (sector_and_cylinder >> 8) as u8]
+    }
+}
+
 impl fmt::Debug for CHS {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("CHS")
@@ -34,6 +42,25 @@ pub struct PartitionEntry {
     pub total_sectors: u32,
 }
 
+impl PartitionEntry {
+    /// The 16 bytes of the entry, as stored in the partition table.
+    pub fn as_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[0] = self.boot_indicator;
+        bytes[1..4].copy_from_slice(&{ self.starting_chs }.as_bytes());
+        bytes[4] = self.partition_type;
+        bytes[5..8].copy_from_slice(&{ self.ending_chs }.as_bytes());
+        bytes[8..12].copy_from_slice(&u32_le(self.relative_sector));
+        bytes[12..16].copy_from_slice(&u32_le(self.total_sectors));
+        bytes
+    }
+}
+
+/// Returns the little-endian bytes of `value`.
+fn u32_le(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 pub struct MasterBootRecord {
@@ -55,6 +82,56 @@ pub enum Error {
     BadSignature,
 }
 
+/// Why `MasterBootRecord::identify_first_supported` found no FAT32 partition.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnsupportedFs {
+    /// Every entry of the partition table is empty.
+    NoPartitions,
+    /// Partition `index` (0-indexed), the first non-empty one, has the
+    /// unsupported type `partition_type`.
+    Unsupported { index: u8, partition_type: u8 },
+}
+
+impl UnsupportedFs {
+    /// A human-readable name for the partition type, if it is a common one.
+    pub fn type_name(&self) -> Option<&'static str> {
+        match *self {
+            UnsupportedFs::NoPartitions => None,
+            UnsupportedFs::Unsupported { partition_type, .. } => match partition_type {
+                0x01 => Some("FAT12"),
+                0x04 | 0x06 | 0x0E => Some("FAT16"),
+                0x05 | 0x0F => Some("an extended partition"),
+                0x07 => Some("exFAT or NTFS"),
+                0x82 => Some("Linux swap"),
+                0x83 => Some("Linux"),
+                0xEE => Some("a GPT protective partition"),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for UnsupportedFs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (*self, self.type_name()) {
+            (UnsupportedFs::NoPartitions, _) => write!(f, "the partition table is empty"),
+            (UnsupportedFs::Unsupported { index, partition_type }, Some(name)) => write!(
+                f,
+                "partition {} is {} (type {:#04x}), not supported",
+                index,
+                name,
+                partition_type
+            ),
+            (UnsupportedFs::Unsupported { index, partition_type }, None) => write!(
+                f,
+                "partition {} has unsupported type {:#04x}",
+                index,
+                partition_type
+            ),
+        }
+    }
+}
+
 impl MasterBootRecord {
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
@@ -64,27 +141,70 @@ impl MasterBootRecord {
     /// Returns `UnknownBootIndicator(n)` if partition `n` contains an invalid
     /// boot indicator. Returns `Io(err)` if the I/O error `err` occured while
     /// reading the MBR.
-    pub fn from<T: BlockDevice>(mut device: T) -> Result<MasterBootRecord, Error> {
+    pub fn from<T: BlockDevice>(device: T) -> Result<MasterBootRecord, Error> {
+        MasterBootRecord::read(device, false)
+    }
+
+    /// Like `from`, but an unused partition entry (of type 0) with an invalid
+    /// boot indicator is accepted and reported as not bootable. Such garbage
+    /// is left behind by some partitioning tools.
+    ///
+    /// # Errors
+    ///
+    /// As for `from`, except that `UnknownBootIndicator(n)` is only returned
+    /// for a partition `n` that is in use.
+    pub fn from_lenient<T: BlockDevice>(device: T) -> Result<MasterBootRecord, Error> {
+        MasterBootRecord::read(device, true)
+    }
+
+    fn read<T: BlockDevice>(mut device: T, lenient: bool) -> Result<MasterBootRecord, Error> {
         let mut buf = [0u8; 512];
-        if device.read_sector(0, &mut buf).map_err(|e| Error::Io(e))? != 512 {
-            return Err(Error::Io(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Got less than 512 bytes when reading MBR.",
-            )));
-        }
-        let mbr = unsafe { mem::transmute::<[u8; 512], MasterBootRecord>(buf) };
+        device.read_sector_exact(0, &mut buf).map_err(|e| Error::Io(e))?;
+        let mut mbr = unsafe { mem::transmute::<[u8; 512], MasterBootRecord>(buf) };
         if mbr.signature != [0x55, 0xAA] {
             return Err(Error::BadSignature);
         }
-        for (index, partition_entry) in mbr.partition_table.iter().enumerate() {
+        for (index, partition_entry) in mbr.partition_table.iter_mut().enumerate() {
             match partition_entry.boot_indicator {
                 0x0 | 0x80 => (),
+                _ if lenient && partition_entry.partition_type == 0 => {
+                    partition_entry.boot_indicator = 0x0;
+                }
                 _ => return Err(Error::UnknownBootIndicator(index as u8)),
             }
         }
         Ok(mbr)
     }
 
+    /// Returns the first FAT32 partition. If there is none, says whether the
+    /// partition table is empty or which unsupported partition comes first.
+    pub fn identify_first_supported(&self) -> Result<&PartitionEntry, UnsupportedFs> {
+        if let Some(entry) = self.first_fat32_partition() {
+            return Ok(entry);
+        }
+        match self.partition_table.iter().position(|entry| entry.partition_type != 0) {
+            Some(index) => Err(UnsupportedFs::Unsupported {
+                index: index as u8,
+                partition_type: self.partition_table[index].partition_type,
+            }),
+            None => Err(UnsupportedFs::NoPartitions),
+        }
+    }
+
+    /// A copy of the 436 bytes of bootstrap code at the start of the MBR.
+    pub fn bootstrap(&self) -> [u8; 436] {
+        self.bootstrap
+    }
+
+    /// A copy of the 64 bytes of the partition table, as stored in the MBR.
+    pub fn raw_partition_table(&self) -> [u8; 64] {
+        let mut table = [0u8; 64];
+        for (raw, entry) in table.chunks_mut(16).zip(self.partition_table.iter()) {
+            raw.copy_from_slice(&entry.as_bytes());
+        }
+        table
+    }
+
     pub fn first_fat32_partition(&self) -> Option<&PartitionEntry> {
         self.first_partition_of(&[0xB, 0xC])
     }
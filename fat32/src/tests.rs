@@ -1,12 +1,13 @@
 extern crate rand;
 
 use std::io::prelude::*;
-use std::io::Cursor;
+use std::io::{Cursor, SeekFrom};
 use std::path::Path;
 
-use vfat::{Shared, VFat, BiosParameterBlock};
+use vfat::{Shared, VFat, VfatConfig, BiosParameterBlock};
 use mbr::{MasterBootRecord, CHS, PartitionEntry};
 use traits::*;
+use testutil::*;
 
 macro check_size($T:ty, $size:expr) {
     assert_eq!(::std::mem::size_of::<$T>(), $size,
@@ -90,6 +91,28 @@ fn check_mbr_boot_indicator() {
     MasterBootRecord::from(Cursor::new(&mut data[..])).unwrap();
 }
 
+#[test]
+fn test_lenient_boot_indicator() {
+    let mut image = MockImage::sample();
+    // Junk in the unused second entry's boot indicator.
+    image.data[446 + 16] = 0x5A;
+
+    let e = MasterBootRecord::from(Cursor::new(&mut image.data[..])).unwrap_err();
+    expect_variant!(e, ::mbr::Error::UnknownBootIndicator(1));
+    let mbr = MasterBootRecord::from_lenient(Cursor::new(&mut image.data[..])).expect("lenient MBR");
+    assert_eq!(mbr.partition_table[1].boot_indicator, 0);
+
+    // An entry in use is still checked.
+    let mut data = image.data.clone();
+    data[446] = 0x5A;
+    let e = MasterBootRecord::from_lenient(Cursor::new(&mut data[..])).unwrap_err();
+    expect_variant!(e, ::mbr::Error::UnknownBootIndicator(0));
+
+    assert!(VFat::from(MockDevice::new(image.data.clone())).is_err());
+    let vfat = image.mount_with(VfatConfig { lenient_boot_indicators: true, ..VfatConfig::default() });
+    assert_eq!(vfat.open_file("/HELLO.TXT").expect("opens").size(), 14);
+}
+
 #[test]
 fn test_mbr() {
     let mut mbr = resource!("mbr.img");
@@ -368,3 +391,2536 @@ fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {}
     f::<Shared<VFat>>();
 }
+
+#[test]
+fn test_mock_image_read() {
+    let mut image = MockImage::new();
+    let contents: Vec<u8> = (0..1300u32).map(|i| i as u8).collect();
+    image.add_file("DATA.BIN", &contents);
+    image.add_file("EMPTY", &[]);
+    let vfat = image.mount();
+
+    let mut file = vfat.open_file("/data.bin").expect("file exists");
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).expect("file is readable");
+    assert_eq!(read, contents);
+
+    assert_eq!(vfat.open_file("/EMPTY").expect("file exists").size(), 0);
+}
+
+#[test]
+fn test_strict_size() {
+    let mut image = MockImage::new();
+    let slot = image.add_file("BIG.TXT", &[0xAB; 700]);
+    image.add_file("EXACT.TXT", &[0xCD; 1024]);
+    // Two clusters are allocated, but the entry claims part of a third.
+    image.set_size(slot, 2 * 512 + 1);
+
+    let lenient = VFat::from(MockDevice::new(image.data.clone())).expect("mock image mounts");
+    assert_eq!(lenient.open_file("/BIG.TXT").expect("lenient open").size(), 1025);
+
+    let strict = image.mount_with(VfatConfig {
+        strict_size: true,
+        ..VfatConfig::default()
+    });
+    let e = strict.open_file("/BIG.TXT").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    strict.open_file("/EXACT.TXT").expect("size matching its chain is accepted");
+}
+
+#[test]
+fn test_verify_allocation() {
+    use vfat::AllocStatus;
+
+    let mut image = MockImage::new();
+    image.add_file("EXACT.BIN", &[1; 1024]);
+    let slack = image.add_file("SLACK.BIN", &[2; 1100]);
+    let short = image.add_file("SHORT.BIN", &[3; 700]);
+    image.add_file("EMPTY", &[]);
+    image.set_size(slack, 10);
+    image.set_size(short, 3 * 512 + 1);
+    let vfat = image.mount();
+
+    let status = |path: &str| vfat.open_file(path).expect("opens").verify_allocation().expect("chain is valid");
+    assert_eq!(status("/EXACT.BIN"), AllocStatus::Exact);
+    assert_eq!(status("/SLACK.BIN"), AllocStatus::OverAllocated { slack: 2 });
+    assert_eq!(status("/SHORT.BIN"), AllocStatus::UnderAllocated { missing: 2 });
+    assert_eq!(status("/EMPTY"), AllocStatus::Exact);
+}
+
+#[test]
+fn test_absurd_size_rejected() {
+    let mut image = MockImage::new();
+    let slot = image.add_file("HUGE.TXT", &[0xAB; 10]);
+    image.add_file("OK.TXT", &[0xCD; 10]);
+    image.set_size(slot, 0xFFFFFFFF);
+
+    // Rejected even without `strict_size`, by path and relative to a
+    // directory alike.
+    let vfat = image.mount();
+    let e = vfat.open_file("/HUGE.TXT").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    let e = vfat.open_dir("/").expect("root").open("HUGE.TXT").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    assert_eq!(vfat.open_file("/OK.TXT").expect("opens").size(), 10);
+}
+
+#[test]
+fn test_allocated_clusters() {
+    let mut image = MockImage::new();
+    image.add_file("TWO.BIN", &[1; 700]);
+    image.add_file("EMPTY", &[]);
+    image.add_file("ONE.BIN", &[2; 10]);
+    image.set_fat(100, 0x0FFFFFF7);
+    let vfat = image.mount();
+
+    let clusters: Vec<u32> = vfat.borrow_mut()
+        .allocated_clusters()
+        .expect("FAT is readable")
+        .map(|cluster| cluster.inner())
+        .collect();
+    assert_eq!(clusters, vec![2, 3, 4, 5, 100]);
+}
+
+#[test]
+fn test_short_name() {
+    let mut image = MockImage::new();
+    image.add_lfn_file("My Document.txt", "MYDOCU~1.TXT", b"hello");
+    image.add_file("PLAIN.TXT", b"world");
+    let vfat = image.mount();
+
+    let file = vfat.open_file("/My Document.txt").expect("file exists");
+    assert_eq!(file.name, "My Document.txt");
+    assert_eq!(file.short_name(), "MYDOCU~1.TXT");
+
+    let file = vfat.open_file("/PLAIN.TXT").expect("file exists");
+    assert_eq!(file.name, "PLAIN.TXT");
+    assert_eq!(file.short_name(), "PLAIN.TXT");
+
+    assert_eq!(vfat.open_dir("/").expect("root exists").short_name(), "");
+}
+
+#[test]
+fn test_unpartitioned() {
+    let mut image = MockImage::new();
+    image.add_file("HELLO.TXT", b"hello");
+    let mut bare = image.data.split_off(MOCK_PARTITION_START * MOCK_SECTOR_SIZE);
+
+    let e = VFat::from(MockDevice::new(bare.clone())).unwrap_err();
+    expect_variant!(e, ::vfat::Error::Unpartitioned);
+
+    // Boot code where a partition table would be: still recognised.
+    bare[446] = 0x41;
+    let e = VFat::from(MockDevice::new(bare.clone())).unwrap_err();
+    expect_variant!(e, ::vfat::Error::Unpartitioned);
+
+    let vfat = VFat::from_unpartitioned(MockDevice::new(bare.clone())).expect("bare volume mounts");
+    let mut contents = String::new();
+    vfat.open_file("/HELLO.TXT")
+        .expect("file exists")
+        .read_to_string(&mut contents)
+        .expect("file is readable");
+    assert_eq!(contents, "hello");
+
+    // The configuration is honoured on a bare volume too.
+    let config = VfatConfig { max_file_size: Some(2), ..VfatConfig::default() };
+    let vfat = VFat::from_unpartitioned_with_config(MockDevice::new(bare), config).expect("bare volume mounts");
+    assert_eq!(vfat.open_file("/HELLO.TXT").unwrap_err().kind(), ::std::io::ErrorKind::InvalidData);
+
+    // An empty MBR is not mistaken for a bare volume.
+    let mut data = vec![0u8; 4096];
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+    let e = VFat::from(Cursor::new(data)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::NotFound);
+}
+
+#[test]
+fn test_stream_position() {
+    let mut image = MockImage::new();
+    image.add_file("DATA.BIN", &[7; 1000]);
+    let vfat = image.mount();
+
+    let mut file = vfat.open_file("/DATA.BIN").expect("file exists");
+    assert_eq!((file.stream_position(), file.stream_len()), (0, 1000));
+
+    let mut buf = [0u8; 100];
+    file.read_exact(&mut buf).expect("file is readable");
+    assert_eq!(file.stream_position(), 100);
+
+    file.seek(SeekFrom::End(-10)).expect("seek within file");
+    assert_eq!((file.stream_position(), file.stream_len()), (990, 1000));
+}
+
+#[test]
+fn test_dir_without_terminator() {
+    let mut image = MockImage::new();
+    for i in 0..16 {
+        image.add_file(&format!("FILE{}.TXT", i), b"x");
+    }
+    // The root directory is full, so it has no terminator, and its chain
+    // points back at itself.
+    image.set_fat(2, 2);
+    let vfat = image.mount();
+    let e = vfat.open_dir("/").expect("root exists").entries().err().expect("cyclic root");
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    let e = vfat.borrow_mut().chain_length(2.into()).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+
+    let mut image = MockImage::new();
+    for i in 0..16 {
+        image.add_file(&format!("FILE{}.TXT", i), b"x");
+    }
+    image.set_fat(MOCK_ROOT, 100);
+    image.set_fat(100, 0x0FFFFFFF);
+    let vfat = image.mount_with(VfatConfig {
+        max_dir_entries: 15,
+        ..VfatConfig::default()
+    });
+    let e = vfat.open_dir("/").expect("root exists").entries().err().expect("too many entries");
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    // Directories are read no further than the limit calls for.
+    assert_eq!(vfat.borrow_mut().read_root_raw().expect("readable").len(), MOCK_SECTOR_SIZE);
+}
+
+#[test]
+fn test_fuzz_root_dir() {
+    use tests::rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    let image = MockImage::new();
+    for _ in 0..16 {
+        let mut data = image.data.clone();
+        let root = MockImage::cluster_offset(2);
+        rng.fill_bytes(&mut data[root..root + MOCK_SECTOR_SIZE]);
+        let vfat = VFat::from(MockDevice::new(data)).expect("mock image mounts");
+        // Garbage entries may or may not parse, but must never panic.
+        if let Ok(entries) = vfat.open_dir("/").expect("root exists").entries() {
+            assert!(entries.count() <= MOCK_SECTOR_SIZE / 32);
+        }
+    }
+}
+
+#[test]
+fn test_clone_handles() {
+    let mut image = MockImage::new();
+    let contents: Vec<u8> = (0..600u32).map(|i| i as u8).collect();
+    image.add_file("DATA.BIN", &contents);
+    let vfat = image.mount();
+
+    let mut file = vfat.open_file("/DATA.BIN").expect("file exists");
+    let mut buf = [0u8; 100];
+    file.read_exact(&mut buf).expect("file is readable");
+
+    let mut clone = file.clone();
+    assert_eq!(clone.stream_position(), 100);
+    let mut rest = Vec::new();
+    clone.read_to_end(&mut rest).expect("clone is readable");
+    assert_eq!(rest, &contents[100..]);
+    assert_eq!(file.stream_position(), 100);
+
+    let dir = vfat.open_dir("/").expect("root exists");
+    let copy = dir.clone();
+    for entry in dir.entries().expect("entries iterator") {
+        copy.find(entry.name()).expect("entry found through clone");
+    }
+}
+
+/// A device that returns at most `limit` bytes per sector read.
+struct ShortReads {
+    inner: Cursor<Vec<u8>>,
+    limit: usize,
+}
+
+impl BlockDevice for ShortReads {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        let limit = ::std::cmp::min(self.limit, buf.len());
+        self.inner.read_sector(n, &mut buf[..limit])
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.inner.write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_read_sector_exact() {
+    let data: Vec<u8> = (0..1536u32).map(|i| (i / 512) as u8 + 1).collect();
+    let mut device = Cursor::new(data.clone());
+    let mut buf = [0u8; 1024];
+    device.read_sector_exact(1, &mut buf).expect("two sectors");
+    assert_eq!(&buf[..], &data[512..]);
+
+    let e = device.read_sector_exact(2, &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::UnexpectedEof);
+
+    let mut data = [0u8; 512];
+    data[510..].copy_from_slice(&[0x55, 0xAA]);
+    let short = ShortReads { inner: Cursor::new(data.to_vec()), limit: 256 };
+    let e = MasterBootRecord::from(short).unwrap_err();
+    expect_variant!(e, ::mbr::Error::Io(ref e) if e.kind() == ::std::io::ErrorKind::UnexpectedEof);
+    let short = ShortReads { inner: Cursor::new(data.to_vec()), limit: 256 };
+    let e = BiosParameterBlock::from(short, 0).unwrap_err();
+    expect_variant!(e, ::vfat::Error::Io(ref e) if e.kind() == ::std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_free_extents() {
+    let mut image = MockImage::new();
+    image.add_file("TWO.BIN", &[1; 700]);
+    image.add_file("ONE.BIN", &[2; 10]);
+    image.set_fat(100, 0x0FFFFFF7);
+    image.set_fat(MOCK_CLUSTERS as u32 + 1, 0x0FFFFFFF);
+    let vfat = image.mount();
+
+    let extents: Vec<(u32, u32)> = vfat.borrow_mut()
+        .free_extents()
+        .expect("FAT is readable")
+        .into_iter()
+        .map(|(cluster, length)| (cluster.inner(), length))
+        .collect();
+    assert_eq!(extents, vec![(6, 94), (101, MOCK_CLUSTERS as u32 - 100)]);
+}
+
+
+#[cfg(feature = "write")]
+#[test]
+fn test_alloc_contiguous() {
+    let mut image = MockImage::new();
+    image.add_file("A.BIN", &[1; 1536]);
+    image.add_file("B.BIN", &[2; 10]);
+    // Free `A.BIN`'s clusters again, leaving a three-cluster hole at 3.
+    for cluster in 3..6 {
+        image.set_fat(cluster, 0);
+    }
+    let device = SharedDevice::new(image.data);
+    let vfat = VFat::from(device.clone()).expect("mock image mounts");
+
+    let first = vfat.borrow_mut().alloc_contiguous(3).expect("hole fits");
+    assert_eq!(first.inner(), 3);
+    let first = vfat.borrow_mut().alloc_contiguous(2).expect("tail fits");
+    assert_eq!(first.inner(), 7);
+    vfat.borrow_mut().sync().expect("sync succeeds");
+
+    for fat in 0..2 {
+        assert_eq!(device.fat(fat, 3), 4);
+        assert_eq!(device.fat(fat, 4), 5);
+        assert_eq!(device.fat(fat, 5), 0x0FFFFFFF);
+        assert_eq!(device.fat(fat, 7), 8);
+        assert_eq!(device.fat(fat, 8), 0x0FFFFFFF);
+    }
+    let fsinfo = (MOCK_PARTITION_START + 1) * MOCK_SECTOR_SIZE;
+    assert_eq!(device.read_u32(fsinfo + 488), MOCK_CLUSTERS as u32 - 5 - 5);
+    assert_eq!(device.read_u32(fsinfo + 492), 9);
+
+    let e = vfat.borrow_mut().alloc_contiguous(0).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    let e = vfat.borrow_mut().alloc_contiguous(MOCK_CLUSTERS as u32).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_alloc_clusters() {
+    use vfat::Cluster;
+
+    let mut image = MockImage::new();
+    image.add_file("A.BIN", &[1; 10]);
+    image.add_file("B.BIN", &[2; 1024]);
+    image.add_file("C.BIN", &[3; 10]);
+    // Free `A.BIN` and the second cluster of `B.BIN`, leaving holes at 3 and 5.
+    image.set_fat(3, 0);
+    image.set_fat(4, 0x0FFFFFFF);
+    image.set_fat(5, 0);
+    let device = SharedDevice::new(image.data);
+    let vfat = VFat::from(device.clone()).expect("mock image mounts");
+
+    let clusters = vfat.borrow_mut().alloc_clusters(4).expect("enough free clusters");
+    let numbers: Vec<u32> = clusters.iter().map(|cluster| cluster.inner()).collect();
+    assert_eq!(numbers, vec![3, 5, 7, 8]);
+    let chain: Vec<Cluster> = vfat.borrow_mut()
+        .chain(clusters[0])
+        .map(|cluster| cluster.expect("chain is valid"))
+        .collect();
+    assert_eq!(chain, clusters);
+    vfat.borrow_mut().sync().expect("sync succeeds");
+
+    for fat in 0..2 {
+        assert_eq!(device.fat(fat, 3), 5);
+        assert_eq!(device.fat(fat, 5), 7);
+        assert_eq!(device.fat(fat, 7), 8);
+        assert_eq!(device.fat(fat, 8), 0x0FFFFFFF);
+    }
+    let fsinfo = (MOCK_PARTITION_START + 1) * MOCK_SECTOR_SIZE;
+    assert_eq!(device.read_u32(fsinfo + 488), MOCK_CLUSTERS as u32 - 5 - 4);
+    assert_eq!(device.read_u32(fsinfo + 492), 9);
+
+    let e = vfat.borrow_mut().alloc_clusters(0).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    let e = vfat.borrow_mut().alloc_clusters(MOCK_CLUSTERS as u32).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+    assert_eq!(device.fat(0, 9), 0, "a failed allocation allocated nothing");
+
+    // Taking the last cluster wraps the next free hint around.
+    let free = vfat.borrow_mut().free_extents().expect("FAT is readable");
+    let count = free.iter().map(|&(_, length)| length).sum();
+    let clusters = vfat.borrow_mut().alloc_clusters(count).expect("exactly enough");
+    assert_eq!(clusters.last().map(|c| c.inner()), Some(vfat.borrow_mut().total_clusters() + 1));
+    vfat.borrow_mut().sync().expect("sync succeeds");
+    assert_eq!(device.read_u32(fsinfo + 492), 2);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_alloc_fragmented_fallback() {
+    let mut image = MockImage::new();
+    // Every other cluster is in use, so no two free clusters are adjacent.
+    for cluster in (3..MOCK_CLUSTERS as u32 + 2).filter(|c| c % 2 == 1) {
+        image.set_fat(cluster, 0x0FFFFFFF);
+    }
+    let data = image.data;
+
+    let vfat = VFat::from(MockDevice::new(data.clone())).expect("mock image mounts");
+    let e = vfat.borrow_mut().alloc_contiguous(3).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+
+    let mut config = VfatConfig::default();
+    config.fragmented_fallback = true;
+    let vfat = VFat::from_with_config(MockDevice::new(data), config).expect("mock image mounts");
+    let first = vfat.borrow_mut().alloc_contiguous(3).expect("fallback allocates");
+    let chain: Vec<u32> = vfat.borrow_mut()
+        .chain(first)
+        .map(|cluster| cluster.expect("chain is valid").inner())
+        .collect();
+    assert_eq!(chain, vec![4, 6, 8]);
+}
+
+#[test]
+fn test_reserved_sector_locations() {
+    let image = MockImage::new();
+    let bpb_offset = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+    let bpb = BiosParameterBlock::from(Cursor::new(image.data.clone()), MOCK_PARTITION_START as u64)
+        .expect("valid EBPB");
+    assert_eq!(bpb.fsinfo_sector(), 1);
+    assert_eq!(bpb.backup_boot_sector(), 6);
+
+    let mut data = image.data.clone();
+    data[bpb_offset + 48..bpb_offset + 50].copy_from_slice(&u16_le(MOCK_RESERVED_SECTORS as u16));
+    let e = BiosParameterBlock::from(Cursor::new(data), MOCK_PARTITION_START as u64).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb(_));
+
+    let mut data = image.data;
+    data[bpb_offset + 50..bpb_offset + 52].copy_from_slice(&u16_le(0xFFFF));
+    let bpb = BiosParameterBlock::from(Cursor::new(data), MOCK_PARTITION_START as u64)
+        .expect("no backup boot sector is valid");
+    assert_eq!(bpb.backup_boot_sector(), 0xFFFF);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_volume_label() {
+    let mut image = MockImage::new();
+    image.add_file("HELLO.TXT", b"hello");
+    // Leftovers past the end marker must stay hidden once a label is added.
+    let ghost = MockImage::root_slot_offset(2);
+    image.data[ghost..ghost + 11].copy_from_slice(b"GHOST   TXT");
+    let device = SharedDevice::new(image.data);
+    let vfat = VFat::from(device.clone()).expect("mock image mounts");
+    let bpb_label = |device: &SharedDevice| {
+        let offset = MOCK_PARTITION_START * MOCK_SECTOR_SIZE + 71;
+        device.0.lock().unwrap().0[offset..offset + 11].to_vec()
+    };
+
+    assert_eq!(vfat.borrow_mut().volume_label().expect("readable"), None);
+    vfat.borrow_mut().set_volume_label("my disk").expect("valid label");
+    assert_eq!(vfat.borrow_mut().volume_label().expect("readable"), Some("MY DISK".to_string()));
+    assert!((&vfat).open_file("/GHOST.TXT").is_err());
+    vfat.borrow_mut().set_volume_label("Photos_2018").expect("valid label");
+    assert_eq!(
+        vfat.borrow_mut().volume_label().expect("readable"),
+        Some("PHOTOS_2018".to_string())
+    );
+    vfat.borrow_mut().sync().expect("sync succeeds");
+    assert_eq!(bpb_label(&device), b"PHOTOS_2018".to_vec());
+    // The label reused the entry it replaced.
+    let label_slot = MockImage::root_slot_offset(1);
+    assert_eq!(&device.0.lock().unwrap().0[label_slot..label_slot + 12], b"PHOTOS_2018\x08");
+
+    // Characters of the OEM code page are allowed, and uppercased.
+    vfat.borrow_mut().set_volume_label("café").expect("valid label");
+    assert_eq!(vfat.borrow_mut().volume_label().expect("readable"), Some("CAFÉ".to_string()));
+    for bad in &["TWELVE CHARS", "A*B", "A.B", " LEADING", "日本"] {
+        let e = vfat.borrow_mut().set_volume_label(bad).unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    }
+
+    vfat.borrow_mut().set_volume_label("").expect("clearing is valid");
+    assert_eq!(vfat.borrow_mut().volume_label().expect("readable"), None);
+    vfat.borrow_mut().sync().expect("sync succeeds");
+    assert_eq!(bpb_label(&device), b"NO NAME    ".to_vec());
+    assert!((&vfat).open_file("/HELLO.TXT").is_ok());
+}
+
+#[test]
+fn test_sample_image() {
+    let vfat = MockImage::sample().mount();
+
+    let names: Vec<String> = vfat.open_dir("/")
+        .expect("root exists")
+        .entries()
+        .expect("root is readable")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["HELLO.TXT", "DATA.BIN", "EMPTY", "Sub Directory"]);
+
+    let names: Vec<String> = vfat.open_dir("/sub directory")
+        .expect("subdirectory exists")
+        .entries()
+        .expect("subdirectory is readable")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec![".", "..", "A long file name.txt", "SHORT.TXT"]);
+
+    let mut contents = String::new();
+    vfat.open_file("/Sub Directory/A long file name.txt")
+        .expect("nested file exists")
+        .read_to_string(&mut contents)
+        .expect("nested file is readable");
+    assert_eq!(contents, "nested\n");
+
+    let mut data = Vec::new();
+    vfat.open_file("/DATA.BIN")
+        .expect("file exists")
+        .read_to_end(&mut data)
+        .expect("file is readable");
+    assert_eq!(data, (0..1300u32).map(|i| i as u8).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_cyclic_chain() {
+    let mut image = MockImage::sample();
+    // `DATA.BIN` occupies clusters 4 to 6; make its last cluster point back
+    // at its first.
+    image.set_fat(6, 4);
+    // The subdirectory at cluster 7 points at itself.
+    image.set_fat(7, 7);
+    let vfat = image.mount();
+
+    let chain: Vec<_> = vfat.borrow_mut().chain(4.into()).collect();
+    assert_eq!(chain.len(), 4);
+    assert!(chain[..3].iter().all(|cluster| cluster.is_ok()));
+    let e = chain[3].as_ref().unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+
+    let mut buf = Vec::new();
+    let e = vfat.borrow_mut().read_chain(4.into(), &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    assert!(buf.len() <= 3 * 512);
+
+    let e = vfat.open_dir("/Sub Directory").expect("entry exists").entries().err().expect("cyclic");
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_cluster() {
+    use vfat::Cluster;
+
+    let cluster = Cluster::new(0x1234);
+    assert_eq!(cluster.number(), 0x1234);
+    assert_eq!(cluster.number(), cluster.inner());
+    assert_eq!(cluster, 0x1234.into());
+    assert_eq!(format!("{}", cluster), "4660");
+    // The reserved upper bits are not part of the number.
+    assert_eq!(Cluster::new(0xF000_0002).number(), 2);
+}
+
+#[test]
+fn test_read_cluster_bounded() {
+    let vfat = MockImage::sample().mount();
+    let mut vfat = vfat.borrow_mut();
+    // HELLO.TXT is 14 bytes at the start of cluster 3; the rest is slack.
+    let mut buf = [0xAAu8; MOCK_SECTOR_SIZE];
+    assert_eq!(vfat.read_cluster_bounded(3.into(), 0, 14, &mut buf).unwrap(), 14);
+    assert_eq!(&buf[..14], b"Hello, world!\n");
+    assert!(buf[14..].iter().all(|&b| b == 0xAA));
+
+    assert_eq!(vfat.read_cluster_bounded(3.into(), 10, 14, &mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], b"ld!\n");
+    assert_eq!(vfat.read_cluster_bounded(3.into(), 14, 14, &mut buf).unwrap(), 0);
+    assert_eq!(vfat.read_cluster_bounded(3.into(), 20, 14, &mut buf).unwrap(), 0);
+    assert_eq!(vfat.read_cluster_bounded(3.into(), 0, 14, &mut buf[..5]).unwrap(), 5);
+    assert_eq!(&buf[..5], b"Hello");
+    // A limit past the cluster is no limit.
+    assert_eq!(vfat.read_cluster_bounded(3.into(), 0, 4096, &mut buf).unwrap(), MOCK_SECTOR_SIZE);
+}
+
+#[test]
+fn test_reserved_region() {
+    use vfat::ReservedLayout;
+
+    let vfat = MockImage::new().mount();
+    assert_eq!(vfat.borrow().reserved_region(), ReservedLayout {
+        boot_sector: 0,
+        fsinfo_sector: Some(1),
+        backup_boot_sector: Some(6),
+        reserved_sectors: MOCK_RESERVED_SECTORS as u64,
+    });
+
+    let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+    let mut image = MockImage::new();
+    image.data[bpb + 48..bpb + 52].copy_from_slice(&[0xFF, 0xFF, 0, 0]);
+    let layout = image.mount().borrow().reserved_region();
+    assert_eq!((layout.fsinfo_sector, layout.backup_boot_sector), (None, None));
+}
+
+#[test]
+fn test_data_region() {
+    let vfat = MockImage::new().mount();
+    let vfat = vfat.borrow();
+    assert_eq!(vfat.data_region(), (MOCK_DATA_START as u64, MOCK_CLUSTERS as u64));
+    assert_eq!(vfat.cluster_start_sector(2.into()), MOCK_DATA_START as u64);
+}
+
+#[test]
+fn test_volume_sizes() {
+    let mut image = MockImage::new();
+    image.add_file("DATA.BIN", &[1; 1300]);
+    image.set_fat(100, 0x0FFFFFF7);
+    let vfat = image.mount();
+    let mut vfat = vfat.borrow_mut();
+
+    let sectors = (MOCK_DATA_START - MOCK_PARTITION_START + MOCK_CLUSTERS) as u64;
+    assert_eq!(vfat.volume_size_bytes(), sectors * MOCK_SECTOR_SIZE as u64);
+    let data = (MOCK_CLUSTERS * MOCK_SECTOR_SIZE) as u64;
+    assert_eq!(vfat.data_size_bytes(), data);
+    // The root, the file's three clusters and the bad cluster are in use.
+    let used = 5 * MOCK_SECTOR_SIZE as u64;
+    assert_eq!(vfat.used_bytes().expect("FAT is readable"), used);
+    assert_eq!(vfat.free_bytes().expect("FAT is readable"), data - used);
+}
+
+#[test]
+fn test_geometry_accessors() {
+    let vfat = MockImage::new().mount();
+    let vfat = vfat.borrow();
+    assert_eq!(vfat.sector_size(), MOCK_SECTOR_SIZE);
+    assert_eq!(vfat.sectors_per_cluster(), 1);
+    assert_eq!(vfat.sectors_per_fat(), MOCK_SECTORS_PER_FAT as u32);
+    assert_eq!(vfat.cluster_size(), vfat.sector_size() * vfat.sectors_per_cluster() as usize);
+}
+
+#[test]
+fn test_total_sectors() {
+    use vfat::BiosParameterBlock;
+
+    let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+    let sectors = (MOCK_DATA_START - MOCK_PARTITION_START + MOCK_CLUSTERS) as u32;
+
+    // Large volume: the 16-bit count is 0 and the 32-bit count is used.
+    let mut image = MockImage::new();
+    let parsed = BiosParameterBlock::from(Cursor::new(&mut image.data[..]), MOCK_PARTITION_START as u64)
+        .expect("valid BPB");
+    assert_eq!(parsed.total_sectors(), sectors);
+
+    // Small volume: the 32-bit count is 0 and the 16-bit count is used.
+    image.data[bpb + 19..bpb + 21].copy_from_slice(&u16_le(0x8000));
+    image.data[bpb + 32..bpb + 36].copy_from_slice(&u32_le(0));
+    let parsed = BiosParameterBlock::from(Cursor::new(&mut image.data[..]), MOCK_PARTITION_START as u64)
+        .expect("valid BPB");
+    assert_eq!(parsed.total_sectors(), 0x8000);
+
+    // The cluster count follows whichever field is in use, and so few
+    // clusters make the volume FAT16.
+    assert_eq!(parsed.fat_type(), Some(::vfat::FatType::Fat16));
+    let e = VFat::from(MockDevice::new(image.data)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb(msg) if msg.contains("FAT16"));
+}
+
+#[test]
+fn test_reentrant_borrow() {
+    let vfat = MockImage::sample().mount();
+    let mut file = vfat.open_file("/HELLO.TXT").expect("file exists");
+    let dir = vfat.open_dir("/").expect("root exists");
+
+    {
+        let _held = vfat.borrow_mut();
+        let mut buf = [0u8; 4];
+        let e = file.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::WouldBlock);
+        let e = dir.entries().err().expect("VFat is borrowed");
+        assert_eq!(e.kind(), ::std::io::ErrorKind::WouldBlock);
+    }
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).expect("borrow was released");
+    assert_eq!(contents, "Hello, world!\n");
+}
+
+#[test]
+fn test_contended_borrow_blocks() {
+    use std::thread;
+    use std::time::Duration;
+
+    let vfat = MockImage::sample().mount();
+    let mut file = vfat.open_file("/HELLO.TXT").expect("file exists");
+    let dir = vfat.open_dir("/").expect("root exists");
+
+    // A borrow held by another thread is waited for, not reported.
+    let held = vfat.borrow_mut();
+    let reader = thread::spawn(move || {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map(|_| contents)
+    });
+    let lister = thread::spawn(move || dir.entries().map(|entries| entries.count()));
+    thread::sleep(Duration::from_millis(50));
+    drop(held);
+    assert_eq!(reader.join().expect("no panic").expect("waits for the borrow"), "Hello, world!\n");
+    assert_eq!(lister.join().expect("no panic").expect("waits for the borrow"), 4);
+}
+
+#[test]
+fn test_poisoned_borrow() {
+    use std::thread;
+
+    let vfat = MockImage::sample().mount();
+    let mut file = vfat.open_file("/HELLO.TXT").expect("file exists");
+    let shared = vfat.clone();
+    let _ = thread::spawn(move || {
+        let _held = shared.borrow_mut();
+        panic!("poison the lock");
+    }).join();
+    let e = file.read(&mut [0u8; 4]).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+}
+
+#[test]
+fn test_sfn_checksum() {
+    use vfat::sfn_checksum;
+
+    assert_eq!(sfn_checksum(b"README  TXT"), 0x73);
+    assert_eq!(sfn_checksum(b"ALONGF~1TXT"), 0x02);
+    assert_eq!(sfn_checksum(b"           "), 0xF7);
+    assert_eq!(sfn_checksum(&[0; 11]), 0x00);
+    assert_eq!(sfn_checksum(&short_name("ALONGF~1.TXT")), 0x02);
+}
+
+#[test]
+fn test_validate_filename() {
+    use vfat::validate_filename;
+    use std::io::ErrorKind::InvalidInput;
+
+    validate_filename("A long file name.txt").expect("valid name");
+    validate_filename(&"a".repeat(255)).expect("255 units is the limit");
+    assert_eq!(validate_filename(&"a".repeat(256)).unwrap_err().kind(), InvalidInput);
+
+    // Each astral-plane character takes two UTF-16 code units.
+    let astral = "\u{1F600}";
+    validate_filename(&astral.repeat(127)).expect("254 units fit");
+    let e = validate_filename(&astral.repeat(128)).unwrap_err();
+    assert_eq!(e.kind(), InvalidInput);
+    let e = validate_filename(&format!("{}{}", "ab", astral.repeat(127))).unwrap_err();
+    assert_eq!(e.kind(), InvalidInput);
+
+    for bad in &["", ".", "..", "a/b", "a\\b", "a:b", "a*b", "a?b", "a\"b", "a<b", "a>b", "a|b", "a\tb"] {
+        assert_eq!(validate_filename(bad).unwrap_err().kind(), InvalidInput, "{:?}", bad);
+    }
+
+    // Without write support, creating anything fails as read-only first.
+    #[cfg(feature = "write")]
+    {
+        let vfat = MockImage::new().mount();
+        let e = vfat.create_file("/a*b.txt").unwrap_err();
+        assert_eq!(e.kind(), InvalidInput);
+        let e = vfat.create_dir("/dir?", false).unwrap_err();
+        assert_eq!(e.kind(), InvalidInput);
+        let e = vfat.rename("/a.txt", "/x|y").unwrap_err();
+        assert_eq!(e.kind(), InvalidInput);
+    }
+}
+
+#[test]
+fn test_faulty_device() {
+    use std::io::ErrorKind;
+
+    let data = MockImage::sample().data;
+    let root = MockImage::cluster_offset(MOCK_ROOT) as u64 / MOCK_SECTOR_SIZE as u64;
+
+    // Without faults, the wrapper is transparent.
+    let vfat = VFat::from(FaultyDevice::new(MockDevice::new(data.clone()))).expect("mounts");
+    assert_eq!(vfat.open_dir("/").expect("root exists").entries().expect("readable").count(), 4);
+
+    let mut device = FaultyDevice::new(MockDevice::new(data.clone()));
+    device.fail_sector(FaultOp::Read, root, ErrorKind::PermissionDenied);
+    let vfat = VFat::from(device).expect("mounts");
+    let e = vfat.open_dir("/").expect("root exists").entries().err().expect("root is bad");
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+    let mut device = FaultyDevice::new(MockDevice::new(data.clone()));
+    device.short_read(0, 100);
+    expect_variant!(VFat::from(device), Err(::vfat::Error::Mbr(_)));
+
+    let mut device = FaultyDevice::new(MockDevice::new(data.clone()));
+    device.fail_after(FaultOp::Read, 1, ErrorKind::TimedOut);
+    let mut buf = [0u8; 512];
+    device.read_sector(0, &mut buf).expect("first read succeeds");
+    let e = device.read_sector(1, &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    device.write_sector(1, &buf).expect("writes are unaffected");
+
+    device.clear_faults();
+    device.fail_after(FaultOp::Write, 0, ErrorKind::Other);
+    assert_eq!(device.write_sector(1, &buf).unwrap_err().kind(), ErrorKind::Other);
+    device.read_sector(1, &mut buf).expect("reads are unaffected");
+    assert_eq!(device.operations(), (3, 2));
+}
+
+#[test]
+fn test_read_all_into() {
+    let mut image = MockImage::new();
+    let contents: Vec<u8> = (0..1300u32).map(|i| (i / 7) as u8).collect();
+    image.add_file("FRAG.BIN", &contents);
+    image.add_file("SMALL.TXT", b"small");
+    // Move the middle cluster of `FRAG.BIN` (3, 4, 5) to cluster 20.
+    let from = MockImage::cluster_offset(4);
+    let middle = image.data[from..from + 512].to_vec();
+    image.cluster_mut(20).copy_from_slice(&middle);
+    image.set_fat(3, 20);
+    image.set_fat(20, 5);
+    image.set_fat(4, 0);
+    let vfat = image.mount();
+
+    let mut buf = Vec::new();
+    let mut file = vfat.open_file("/FRAG.BIN").expect("file exists");
+    assert_eq!(file.read_all_into(&mut buf).expect("readable"), 1300);
+    assert_eq!(buf, contents);
+    assert_eq!(file.read_all_into(&mut buf).expect("at end"), 0);
+    assert!(buf.is_empty());
+
+    file.seek(SeekFrom::Start(700)).expect("seekable");
+    assert_eq!(file.read_all_into(&mut buf).expect("readable"), 600);
+    assert_eq!(&buf[..], &contents[700..]);
+
+    let capacity = buf.capacity();
+    let mut small = vfat.open_file("/SMALL.TXT").expect("file exists");
+    assert_eq!(small.read_all_into(&mut buf).expect("readable"), 5);
+    assert_eq!(buf, b"small");
+    assert_eq!(buf.capacity(), capacity);
+}
+
+#[test]
+fn test_attributes_raw() {
+    use vfat::Attributes;
+
+    let vfat = MockImage::sample().mount();
+    let file = vfat.open("/HELLO.TXT").expect("file exists");
+    assert_eq!(file.metadata().attributes.raw(), Attributes::ARCHIVE);
+    let dir = vfat.open("/Sub Directory").expect("directory exists");
+    assert_eq!(dir.metadata().attributes.raw(), Attributes::DIRECTORY);
+    assert_eq!(Attributes::LFN, 0x0F);
+}
+
+#[test]
+fn test_metadata_attributes() {
+    use traits::Metadata;
+
+    let vfat = MockImage::sample().mount();
+    let file = vfat.open("/HELLO.TXT").expect("file exists");
+    let metadata = file.metadata();
+    assert!(metadata.archive() && !metadata.system() && !metadata.volume_id() && !metadata.is_dir());
+    let dir = vfat.open("/Sub Directory").expect("directory exists");
+    let metadata = dir.metadata();
+    assert!(metadata.is_dir() && !metadata.archive() && !metadata.system());
+
+    let mut image = MockImage::new();
+    image.add_file("KERNEL.SYS", b"");
+    image.data[MockImage::root_slot_offset(0) + 11] = 0x04;
+    image.add_file("MY DISK", b"");
+    image.data[MockImage::root_slot_offset(1) + 11] = 0x08;
+    let vfat = image.mount();
+    assert!(vfat.open("/KERNEL.SYS").expect("file exists").metadata().system());
+    let label = vfat.open_dir("/").expect("root").entries().expect("entries").nth(1).expect("label");
+    assert!(label.metadata().volume_id() && !label.metadata().is_dir());
+}
+
+#[test]
+fn test_dirty_flag() {
+    // Mounting alone, with or without write support, changes nothing.
+    let vfat = MockImage::new().mount();
+    assert!(!vfat.borrow_mut().is_dirty().expect("FAT is readable"));
+
+    let mut image = MockImage::new();
+    image.set_fat(1, 0x07FFFFFF);
+    let vfat = image.mount();
+    assert!(vfat.borrow_mut().is_dirty().expect("FAT is readable"));
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_dirty_flag_mount_unmount() {
+    let device = SharedDevice::new(MockImage::new().data);
+    assert_eq!(device.fat(0, 1), 0x0FFFFFFF);
+
+    let vfat = VFat::from(device.clone()).expect("mock image mounts");
+    assert!(!vfat.borrow_mut().is_dirty().expect("FAT is readable"));
+    assert_eq!(device.fat(0, 1), 0x0FFFFFFF);
+
+    // The first change marks the volume dirty on the device at once.
+    vfat.borrow_mut().alloc_contiguous(1).expect("allocates");
+    assert!(vfat.borrow_mut().is_dirty().expect("FAT is readable"));
+    assert_eq!(device.fat(0, 1), 0x07FFFFFF);
+    assert_eq!(device.fat(1, 1), 0x07FFFFFF);
+
+    vfat.borrow_mut().unmount().expect("unmount succeeds");
+    assert!(!vfat.borrow_mut().is_dirty().expect("FAT is readable"));
+    assert_eq!(device.fat(0, 1), 0x0FFFFFFF);
+    assert_eq!(device.fat(1, 1), 0x0FFFFFFF);
+
+    // Changes after an unmount mark it dirty again.
+    vfat.borrow_mut().alloc_contiguous(1).expect("allocates");
+    assert_eq!(device.fat(0, 1), 0x07FFFFFF);
+}
+
+/// A device that refuses every write.
+struct ReadOnlyDevice(MockDevice);
+
+impl BlockDevice for ReadOnlyDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.0.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> ::std::io::Result<usize> {
+        Err(::std::io::Error::new(::std::io::ErrorKind::PermissionDenied, "Read-only device."))
+    }
+}
+
+#[test]
+fn test_mount_read_only_device() {
+    let vfat = VFat::from(ReadOnlyDevice(MockDevice::new(MockImage::sample().data))).expect("mounts");
+    let mut contents = String::new();
+    vfat.open_file("/HELLO.TXT").expect("file exists").read_to_string(&mut contents).expect("readable");
+    assert_eq!(contents, "Hello, world!\n");
+    assert!(!vfat.borrow_mut().is_dirty().expect("FAT is readable"));
+    #[cfg(feature = "write")]
+    {
+        vfat.borrow_mut().unmount().expect("nothing to write");
+        let e = vfat.borrow_mut().alloc_contiguous(1).unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::PermissionDenied);
+    }
+}
+
+#[test]
+fn test_dir_entry_parser() {
+    use vfat::DirEntryParser;
+
+    fn regular(name: &str, attributes: u8, first: u32, size: u32) -> [u8; 32] {
+        let mut entry = [0u8; 32];
+        entry[..11].copy_from_slice(&short_name(name));
+        entry[11] = attributes;
+        entry[20..22].copy_from_slice(&u16_le((first >> 16) as u16));
+        entry[26..28].copy_from_slice(&u16_le(first as u16));
+        entry[28..32].copy_from_slice(&u32_le(size));
+        entry
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&regular("PLAIN.TXT", 0x20, 0x12345, 10));
+    let mut deleted = regular("GONE.TXT", 0x20, 9, 1);
+    deleted[0] = 0xE5;
+    bytes.extend_from_slice(&deleted);
+    for lfn in lfn_entries("A rather long directory name", &short_name("ARATHE~1")) {
+        bytes.extend_from_slice(&lfn);
+    }
+    bytes.extend_from_slice(&regular("ARATHE~1", 0x10, 7, 0));
+    // An LFN entry with an invalid sequence number spoils its name.
+    let mut bad = lfn_entries("Ignored", &short_name("IGNORED"))[0];
+    bad[0] = 0x40;
+    bytes.extend_from_slice(&bad);
+    bytes.extend_from_slice(&regular("SHORT", 0x20, 0, 0));
+    bytes.extend_from_slice(&[0u8; 32]);
+    bytes.extend_from_slice(&regular("AFTER.TXT", 0x20, 3, 1));
+
+    let entries: Vec<_> = DirEntryParser::from_bytes(&bytes).collect();
+    let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+    assert_eq!(names, vec!["PLAIN.TXT", "A rather long directory name", "SHORT"]);
+    assert_eq!(entries[0].first_cluster.inner(), 0x12345);
+    assert_eq!(entries[0].size, 10);
+    assert_eq!(entries[1].short_name, "ARATHE~1");
+    assert!(entries[1].metadata.attributes.directory());
+
+    assert_eq!(DirEntryParser::from_bytes(&bytes[..31]).count(), 0);
+}
+
+#[test]
+fn test_dir_entry_decoding() {
+    use traits::Timestamp;
+    use vfat::DirEntryParser;
+
+    let mut entry = [0u8; 32];
+    entry[..11].copy_from_slice(&short_name("DATES.TXT"));
+    entry[11] = 0x21;
+    // Modified 2018-03-14 09:26:54.
+    entry[22..24].copy_from_slice(&u16_le((9 << 11) | (26 << 5) | 27));
+    entry[24..26].copy_from_slice(&u16_le(((2018 - 1980) << 9) | (3 << 5) | 14));
+    entry[20..22].copy_from_slice(&u16_le(0x0001));
+    entry[26..28].copy_from_slice(&u16_le(0x2345));
+    entry[28..32].copy_from_slice(&u32_le(0x01020304));
+
+    // The slots start at an odd address and are followed by a partial slot
+    // that doesn't start with the end marker.
+    let mut bytes = vec![0xAAu8];
+    for lfn in lfn_entries("Some dates.txt", &short_name("DATES.TXT")) {
+        bytes.extend_from_slice(&lfn);
+    }
+    bytes.extend_from_slice(&entry);
+    bytes.extend_from_slice(&[b'X'; 17]);
+    assert_ne!((bytes[1..].as_ptr() as usize) % 2, 0);
+
+    let entries: Vec<_> = DirEntryParser::from_bytes(&bytes[1..]).collect();
+    assert_eq!(entries.len(), 1);
+    let parsed = &entries[0];
+    assert_eq!(parsed.name, "Some dates.txt");
+    assert_eq!(parsed.short_name, "DATES.TXT");
+    assert_eq!(parsed.first_cluster.inner(), 0x12345);
+    assert_eq!(parsed.size, 0x01020304);
+    assert!(parsed.metadata.attributes.read_only() && parsed.metadata.attributes.archive());
+    let modified = parsed.metadata.modified_time;
+    assert_eq!((modified.year(), modified.month(), modified.day()), (2018, 3, 14));
+    assert_eq!((modified.hour(), modified.minute(), modified.second()), (9, 26, 54));
+}
+
+#[test]
+fn test_orphaned_lfn_runs() {
+    use vfat::DirEntryParser;
+
+    fn regular(name: &str) -> [u8; 32] {
+        let mut entry = [0u8; 32];
+        entry[..11].copy_from_slice(&short_name(name));
+        entry[11] = 0x20;
+        entry
+    }
+
+    // A run interrupted by a deleted entry, then a second run cut short by
+    // the start of a third, complete one.
+    let mut bytes = Vec::new();
+    for lfn in lfn_entries("Interrupted by a deletion", &short_name("INTERR~1")) {
+        bytes.extend_from_slice(&lfn);
+    }
+    let mut deleted = regular("GONE.TXT");
+    deleted[0] = 0xE5;
+    bytes.extend_from_slice(&deleted);
+    bytes.extend_from_slice(&regular("PLAIN.TXT"));
+    let abandoned = lfn_entries("An abandoned name that is long", &short_name("ANABAN~1"));
+    bytes.extend_from_slice(&abandoned[0]);
+    for lfn in lfn_entries("Kept", &short_name("KEPT")) {
+        bytes.extend_from_slice(&lfn);
+    }
+    bytes.extend_from_slice(&regular("KEPT"));
+    // The directory ends on a dangling LFN entry.
+    for lfn in lfn_entries("Dangling", &short_name("DANGLING")) {
+        bytes.extend_from_slice(&lfn);
+    }
+    bytes.extend_from_slice(&[0u8; 32]);
+
+    let mut parser = DirEntryParser::from_bytes(&bytes);
+    let names: Vec<String> = parser.by_ref().map(|entry| entry.name).collect();
+    assert_eq!(names, vec!["PLAIN.TXT", "Kept"]);
+    assert_eq!(parser.orphaned_lfn_runs(), 3);
+
+    // Without a terminator, the end of the entries ends the run just the same.
+    let end = bytes.len() - 32;
+    let mut parser = DirEntryParser::from_bytes(&bytes[..end]);
+    assert_eq!(parser.by_ref().count(), 2);
+    assert_eq!(parser.orphaned_lfn_runs(), 3);
+
+    // Listing a directory reports them too.
+    let mut image = MockImage::new();
+    image.add_file("A.TXT", b"a");
+    let dangling = MockImage::root_slot_offset(1);
+    image.data[dangling..dangling + 32].copy_from_slice(&lfn_entries("Dangling", &short_name("DANGLING"))[0]);
+    let vfat = image.mount();
+    let root = vfat.open_dir("/").expect("root");
+    let mut entries = root.entries().expect("listable");
+    assert_eq!(entries.by_ref().count(), 1);
+    assert_eq!(entries.orphaned_lfn_runs(), 1);
+    let mut entries = root.entries_streaming().expect("listable");
+    assert_eq!(entries.by_ref().count(), 1);
+    assert_eq!(entries.orphaned_lfn_runs(), 1);
+}
+
+#[test]
+fn test_entry_iter_fused() {
+    use vfat::DirEntryParser;
+
+    // A regular entry, then the end-of-directory marker, then a regular entry
+    // that must never be reached.
+    let mut bytes = Vec::new();
+    for name in &["FIRST.TXT", "", "AFTER.TXT"] {
+        let mut entry = [0u8; 32];
+        if !name.is_empty() {
+            entry[..11].copy_from_slice(&short_name(name));
+            entry[11] = 0x20;
+        }
+        bytes.extend_from_slice(&entry);
+    }
+    let mut parser = DirEntryParser::from_bytes(&bytes);
+    assert_eq!(parser.size_hint(), (0, Some(3)));
+    assert_eq!(parser.next().expect("first entry").name, "FIRST.TXT");
+    assert_eq!(parser.size_hint(), (0, Some(2)));
+    for _ in 0..3 {
+        assert!(parser.next().is_none());
+        assert_eq!(parser.size_hint(), (0, Some(0)));
+    }
+
+    let vfat = MockImage::sample().mount();
+    let mut entries = vfat.open_dir("/").expect("root").entries().expect("entries");
+    let (_, upper) = entries.size_hint();
+    let count = entries.by_ref().count();
+    assert!(count == 4 && upper.expect("upper bound") >= count);
+    for _ in 0..3 {
+        assert!(entries.next().is_none());
+    }
+    assert_eq!(entries.size_hint(), (0, Some(0)));
+}
+
+#[test]
+fn test_identify_first_supported() {
+    use mbr::UnsupportedFs;
+
+    let mut data = MockImage::new().data;
+    {
+        let mbr = MasterBootRecord::from(Cursor::new(data.clone())).expect("valid MBR");
+        let partition = mbr.identify_first_supported().expect("FAT32 partition");
+        assert_eq!({ partition.relative_sector }, MOCK_PARTITION_START as u32);
+    }
+
+    data[446 + 4] = 0x07;
+    let mbr = MasterBootRecord::from(Cursor::new(data.clone())).expect("valid MBR");
+    let unsupported = mbr.identify_first_supported().unwrap_err();
+    assert_eq!(unsupported, UnsupportedFs::Unsupported { index: 0, partition_type: 0x07 });
+    assert_eq!(unsupported.to_string(), "partition 0 is exFAT or NTFS (type 0x07), not supported");
+    let e = VFat::from(MockDevice::new(data.clone())).unwrap_err();
+    expect_variant!(e, ::vfat::Error::UnsupportedFs(UnsupportedFs::Unsupported { index: 0, .. }));
+
+    data[446 + 4] = 0;
+    data[446 + 16 + 4] = 0x42;
+    let mbr = MasterBootRecord::from(Cursor::new(data.clone())).expect("valid MBR");
+    let unsupported = mbr.identify_first_supported().unwrap_err();
+    assert_eq!(unsupported.to_string(), "partition 1 has unsupported type 0x42");
+
+    data[446 + 16 + 4] = 0;
+    let mbr = MasterBootRecord::from(Cursor::new(data)).expect("valid MBR");
+    assert_eq!(mbr.identify_first_supported().unwrap_err(), UnsupportedFs::NoPartitions);
+}
+
+#[test]
+fn test_read_chain_limited() {
+    let mut image = MockImage::new();
+    let contents: Vec<u8> = (0..2000u32).map(|i| i as u8).collect();
+    image.add_file("BIG.BIN", &contents);
+    // The chain runs 3, 4, 5, 6; corrupt what follows the second cluster.
+    image.set_fat(5, 0x0FFFFFF7);
+    let vfat = image.mount();
+
+    let mut buf = Vec::new();
+    let read = vfat.borrow_mut().read_chain_limited(3.into(), 2, &mut buf).expect("two clusters");
+    assert_eq!(read, 1024);
+    assert_eq!(&buf[..read], &contents[..1024]);
+
+    let mut buf = Vec::new();
+    let read = vfat.borrow_mut().read_chain_limited(3.into(), 0, &mut buf).expect("no clusters");
+    assert_eq!(read, 0);
+
+    let mut buf = Vec::new();
+    let e = vfat.borrow_mut().read_chain_limited(3.into(), 4, &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+}
+
+/// A device with 1024-byte sectors.
+struct LargeSectors(Cursor<Vec<u8>>);
+
+impl BlockDevice for LargeSectors {
+    fn sector_size(&self) -> u64 {
+        1024
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        let len = ::std::cmp::min(1024, buf.len());
+        self.0.seek(SeekFrom::Start(n * 1024))?;
+        self.0.read_exact(&mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        let len = ::std::cmp::min(1024, buf.len());
+        self.0.seek(SeekFrom::Start(n * 1024))?;
+        self.0.write_all(&buf[..len])?;
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_sector_size_mismatch() {
+    let mut data = MockImage::new().data;
+    // The EBPB is at byte 4096, i.e. sector 4 of a device with 1024-byte
+    // sectors, but it declares 512-byte logical sectors.
+    data[446 + 8..446 + 12].copy_from_slice(&u32_le(4));
+    let e = VFat::from(LargeSectors(Cursor::new(data))).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb(_));
+}
+
+#[test]
+fn test_fat16_fields_rejected() {
+    let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+
+    let mut image = MockImage::new();
+    image.data[bpb + 17..bpb + 19].copy_from_slice(&u16_le(512));
+    let e = VFat::from(MockDevice::new(image.data)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb(_));
+
+    let mut image = MockImage::new();
+    image.data[bpb + 22..bpb + 24].copy_from_slice(&u16_le(40));
+    let e = VFat::from(MockDevice::new(image.data)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb(_));
+}
+
+#[test]
+fn test_oversized_fat_rejected() {
+    let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+
+    let mut image = MockImage::new();
+    image.data[bpb + 36..bpb + 40].copy_from_slice(&u32_le(0x10000000));
+    let e = VFat::from(MockDevice::new(image.data)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb(_));
+
+    // FATs exactly as large as the volume allows pass this check, only to
+    // leave no clusters for a FAT32 volume.
+    let mut image = MockImage::new();
+    let sectors = (MOCK_DATA_START - MOCK_PARTITION_START + MOCK_CLUSTERS) as u32;
+    let fat_size = (sectors - MOCK_RESERVED_SECTORS as u32) / 2;
+    image.data[bpb + 36..bpb + 40].copy_from_slice(&u32_le(fat_size));
+    let e = VFat::from(MockDevice::new(image.data)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb(msg) if msg.contains("Not a FAT32 volume"));
+}
+
+/// A device with 4096-byte sectors that refuses to read partial sectors.
+struct NativeSectors(Cursor<Vec<u8>>);
+
+impl BlockDevice for NativeSectors {
+    fn sector_size(&self) -> u64 {
+        4096
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        if buf.len() < 4096 {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, "Short buffer."));
+        }
+        self.0.seek(SeekFrom::Start(n * 4096))?;
+        self.0.read_exact(&mut buf[..4096])?;
+        Ok(4096)
+    }
+
+    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> ::std::io::Result<usize> {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn test_bpb_native_sectors() {
+    let image = MockImage::new();
+    let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+    // The EBPB at the start of sector 1, followed by the rest of the sector.
+    let mut data = vec![0u8; 2 * 4096];
+    data[4096..].copy_from_slice(&image.data[bpb..bpb + 4096]);
+
+    let parsed = BiosParameterBlock::from(NativeSectors(Cursor::new(data.clone())), 1)
+        .expect("EBPB read from a whole sector");
+    assert_eq!({ parsed.bytes_per_sector }, MOCK_SECTOR_SIZE as u16);
+
+    data[4096 + 510] = 0;
+    let e = BiosParameterBlock::from(NativeSectors(Cursor::new(data)), 1).unwrap_err();
+    expect_variant!(e, ::vfat::Error::BadSignature);
+}
+
+#[test]
+fn test_boot_code() {
+    let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+    let mut data = MockImage::new().data;
+    for i in 0..436 {
+        data[i] = i as u8;
+    }
+    for i in 0..420 {
+        data[bpb + 90 + i] = (i * 3) as u8;
+    }
+
+    let mbr = MasterBootRecord::from(Cursor::new(data.clone())).expect("valid MBR");
+    let bootstrap = mbr.bootstrap();
+    assert!(bootstrap.iter().enumerate().all(|(i, &b)| b == i as u8));
+
+    let parsed = BiosParameterBlock::from(Cursor::new(data), MOCK_PARTITION_START as u64)
+        .expect("valid BPB");
+    let boot_code = parsed.boot_code();
+    assert!(boot_code.iter().enumerate().all(|(i, &b)| b == (i * 3) as u8));
+}
+
+#[test]
+fn test_raw_partition_table() {
+    let mut data = MockImage::new().data;
+    data[446 + 16..446 + 32].copy_from_slice(&[0x80, 1, 2, 3, 0x83, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+    let mbr = MasterBootRecord::from(Cursor::new(data.clone())).expect("valid MBR");
+    assert_eq!(&mbr.raw_partition_table()[..], &data[446..510]);
+    for (i, entry) in mbr.partition_table.iter().enumerate() {
+        assert_eq!(&entry.as_bytes()[..], &data[446 + i * 16..446 + (i + 1) * 16]);
+    }
+}
+
+#[test]
+fn test_walk() {
+    let mut image = MockImage::sample();
+    let bad = image.add_dir(MOCK_ROOT, None, "BAD");
+    image.set_fat(bad, 0x0FFFFFF7);
+    let vfat = image.mount();
+
+    let mut walked = Vec::new();
+    let mut errors = 0;
+    for entry in vfat.walk("/").expect("root exists") {
+        match entry {
+            Ok(entry) => walked.push((entry.path, entry.is_dir, entry.size)),
+            Err(_) => errors += 1,
+        }
+    }
+    let expected: Vec<(::std::path::PathBuf, bool, u64)> = vec![
+        ("/HELLO.TXT".into(), false, 14),
+        ("/DATA.BIN".into(), false, 1300),
+        ("/EMPTY".into(), false, 0),
+        ("/Sub Directory".into(), true, 0),
+        ("/Sub Directory/A long file name.txt".into(), false, 7),
+        ("/Sub Directory/SHORT.TXT".into(), false, 6),
+        ("/BAD".into(), true, 0),
+    ];
+    assert_eq!(walked, expected);
+    assert_eq!(errors, 1);
+
+    let entry = vfat.walk("/Sub Directory")
+        .expect("subdirectory exists")
+        .next()
+        .expect("subdirectory is not empty")
+        .expect("entry is readable");
+    assert_eq!(entry.path, Path::new("/Sub Directory/A long file name.txt"));
+    assert!(entry.metadata.attributes.archive());
+    assert_eq!(entry.entry.name(), "A long file name.txt");
+}
+
+#[test]
+fn test_validate_root() {
+    let config = VfatConfig {
+        validate_root: true,
+        ..VfatConfig::default()
+    };
+    MockImage::sample().mount_with(config.clone());
+
+    for &next in &[0x0FFFFFF7, 0, MOCK_CLUSTERS as u32 + 2, 2] {
+        let mut image = MockImage::sample();
+        image.set_fat(MOCK_ROOT, next);
+        let vfat = VFat::from(MockDevice::new(image.data.clone())).expect("unchecked mount");
+        let e = vfat.borrow_mut().validate_root().unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+
+        let e = VFat::from_with_config(MockDevice::new(image.data), config.clone()).unwrap_err();
+        expect_variant!(e, ::vfat::Error::Io(ref e) if e.kind() == ::std::io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_oem_codec() {
+    use vfat::OemCodec;
+
+    let mut image = MockImage::new();
+    let accented = image.add_file("XB.TXT", b"1");
+    let kanji_lead = image.add_file("XC.TXT", b"2");
+    let long = image.add_lfn_file("\u{e9}t\u{e9}.txt", "XD.TXT", b"3");
+    image.data[MockImage::root_slot_offset(accented)] = 0x80;
+    image.data[MockImage::root_slot_offset(kanji_lead)] = 0x05;
+    image.data[MockImage::root_slot_offset(long)] = 0x82;
+
+    let names = |vfat: Shared<VFat>| -> Vec<(String, String)> {
+        vfat.open_dir("/")
+            .expect("root exists")
+            .entries()
+            .expect("root is readable")
+            .map(|entry| {
+                let short = match entry {
+                    ::vfat::Entry::File(ref file) => file.short_name(),
+                    ::vfat::Entry::Dir(ref dir) => dir.short_name(),
+                };
+                (entry.name().to_string(), short)
+            })
+            .collect()
+    };
+
+    let default = names(VFat::from(MockDevice::new(image.data.clone())).expect("mounts"));
+    assert_eq!(default[0].0, "\u{c7}B.TXT");
+    assert_eq!(default[1].0, "\u{3c3}C.TXT");
+    // The long name is unaffected; only the short name is decoded.
+    assert_eq!(default[2], ("\u{e9}t\u{e9}.txt".to_string(), "\u{e9}D.TXT".to_string()));
+
+    let mut table = ['?'; 128];
+    table[0] = '\u{416}';
+    let config = VfatConfig {
+        oem_codec: OemCodec::from_table(table),
+        ..VfatConfig::default()
+    };
+    let custom = names(image.mount_with(config));
+    assert_eq!(custom[0].0, "\u{416}B.TXT");
+    assert_eq!(custom[1].0, "?C.TXT");
+    assert_eq!(custom[2].0, "\u{e9}t\u{e9}.txt");
+}
+
+#[test]
+fn test_extract() {
+    use std::fs;
+
+    let host = ::std::env::temp_dir().join(format!("fat32-extract-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&host);
+    fs::create_dir_all(&host).expect("temporary directory");
+
+    let vfat = MockImage::sample().mount();
+    let copied = vfat.extract("/HELLO.TXT", host.join("hello.txt")).expect("file extracts");
+    assert_eq!(copied, 14);
+    assert_eq!(fs::read(host.join("hello.txt")).expect("host file"), b"Hello, world!\n");
+
+    let copied = vfat.extract("/", host.join("root")).expect("tree extracts");
+    assert_eq!(copied, 14 + 1300 + 7 + 6);
+    let nested = host.join("root/Sub Directory/A long file name.txt");
+    assert_eq!(fs::read(nested).expect("nested host file"), b"nested\n");
+    assert_eq!(fs::read(host.join("root/EMPTY")).expect("empty host file").len(), 0);
+
+    // A long name that climbs out of the destination is refused.
+    let mut image = MockImage::new();
+    image.add_lfn_file("../escaped", "ESCAPED", b"x");
+    let e = image.mount().extract("/", host.join("evil")).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+
+    fs::remove_dir_all(&host).expect("cleanup");
+}
+
+/// Counts the reads made of the device it wraps.
+struct CountingDevice(MockDevice, ::std::sync::Arc<::std::sync::atomic::AtomicUsize>);
+
+impl BlockDevice for CountingDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.1.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst);
+        self.0.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.0.write_sector(n, buf)
+    }
+}
+
+/// Allocating `count` clusters at once reads far fewer FAT sectors than
+/// allocating them one at a time: the FAT is scanned once instead of once per
+/// cluster.
+#[cfg(feature = "write")]
+#[test]
+fn test_alloc_clusters_cost() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const COUNT: u32 = 8;
+
+    // With a small cache, every scan of the FAT goes to the device.
+    fn reads_to_allocate<F: FnOnce(&mut VFat)>(allocate: F) -> usize {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let device = CountingDevice(MockDevice::new(MockImage::sample().data), reads.clone());
+        let mut config = VfatConfig::default();
+        config.cache_capacity = Some(4);
+        let vfat = VFat::from_with_config(device, config).expect("mock image mounts");
+        let before = reads.load(Ordering::SeqCst);
+        allocate(&mut vfat.borrow_mut());
+        reads.load(Ordering::SeqCst) - before
+    }
+    let one_at_a_time = reads_to_allocate(|vfat| for _ in 0..COUNT {
+        vfat.alloc_contiguous(1).expect("free cluster");
+    });
+    let at_once = reads_to_allocate(|vfat| {
+        vfat.alloc_clusters(COUNT).expect("free clusters");
+    });
+    assert!(at_once * (COUNT as usize - 1) < one_at_a_time, "{} vs {}", at_once, one_at_a_time);
+}
+
+/// Reading on through a file looks up one FAT entry per cluster boundary,
+/// rather than walking the chain from its start for every read.
+#[test]
+fn test_read_follows_cursor() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Each cluster's FAT entry lies in a different FAT sector, and each
+    // cluster is filled with its index in the chain.
+    const CLUSTERS: u32 = 16;
+    let mut image = MockImage::new();
+    let slot = image.add_file("FRAG.BIN", &[0; MOCK_SECTOR_SIZE]);
+    let clusters = (0..CLUSTERS).map(|i| 3 + i * 128).collect::<Vec<u32>>();
+    for (i, pair) in clusters.windows(2).enumerate() {
+        image.set_fat(pair[0], pair[1]);
+        for b in image.cluster_mut(pair[0]).iter_mut() {
+            *b = i as u8;
+        }
+    }
+    image.set_fat(clusters[CLUSTERS as usize - 1], 0x0FFFFFFF);
+    for b in image.cluster_mut(clusters[CLUSTERS as usize - 1]).iter_mut() {
+        *b = CLUSTERS as u8 - 1;
+    }
+    image.set_size(slot, CLUSTERS * MOCK_SECTOR_SIZE as u32);
+
+    // With a one-sector cache, every FAT lookup goes to the device.
+    let reads = Arc::new(AtomicUsize::new(0));
+    let device = CountingDevice(MockDevice::new(image.data), reads.clone());
+    let mut config = VfatConfig::default();
+    config.cache_capacity = Some(1);
+    let vfat = VFat::from_with_config(device, config).expect("mock image mounts");
+    let mut file = vfat.open_file("/FRAG.BIN").expect("file exists");
+    let before = reads.load(Ordering::SeqCst);
+    let mut buf = [0u8; MOCK_SECTOR_SIZE];
+    for i in 0..CLUSTERS {
+        file.read_exact(&mut buf).expect("readable");
+        assert!(buf.iter().all(|&b| b == i as u8));
+    }
+    // A few sectors per cluster; walking from the start every time takes
+    // about CLUSTERS * CLUSTERS / 2.
+    let used = reads.load(Ordering::SeqCst) - before;
+    assert!(used <= 4 * CLUSTERS as usize, "{} reads", used);
+
+    // Seeking back and reading clusters out of order still finds them.
+    file.seek(SeekFrom::Start(3 * MOCK_SECTOR_SIZE as u64)).expect("seekable");
+    file.read_exact(&mut buf).expect("readable");
+    assert_eq!(buf[0], 3);
+    file.read_nth_cluster(9, &mut buf).expect("readable");
+    assert_eq!(buf[0], 9);
+    file.read_nth_cluster(1, &mut buf).expect("readable");
+    assert_eq!(buf[0], 1);
+}
+
+#[test]
+fn test_warm_cache_for_tree() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let reads = Arc::new(AtomicUsize::new(0));
+    let device = CountingDevice(MockDevice::new(MockImage::sample().data), reads.clone());
+    let vfat = VFat::from(device).expect("mock image mounts");
+    vfat.borrow_mut().warm_cache_for_tree(Path::new("/")).expect("tree warms");
+
+    let before = reads.load(Ordering::SeqCst);
+    let paths: Vec<_> = vfat.walk("/").expect("walk").map(|entry| entry.expect("entry").path).collect();
+    assert_eq!(paths.len(), 6);
+    assert_eq!(reads.load(Ordering::SeqCst), before, "listing hit the device");
+
+    // Reading file data still goes to the device: it was not warmed.
+    let mut contents = String::new();
+    vfat.open_file("/HELLO.TXT").expect("file").read_to_string(&mut contents).expect("read");
+    assert!(reads.load(Ordering::SeqCst) > before);
+
+    let mut vfat = vfat.borrow_mut();
+    let e = vfat.warm_cache_for_tree(Path::new("/HELLO.TXT")).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    let e = vfat.warm_cache_for_tree(Path::new("/missing")).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::NotFound);
+    vfat.warm_cache_for_tree(Path::new("/sub directory")).expect("subtree warms");
+}
+
+#[test]
+fn test_cache_capacity() {
+    let mut config = VfatConfig::default();
+    config.cache_capacity = Some(4);
+    let vfat = MockImage::sample().mount_with(config);
+    vfat.borrow_mut().warm_cache_for_tree(Path::new("/")).expect("warming stops when full");
+
+    // Evicted sectors are read again on demand.
+    assert_eq!(vfat.walk("/").expect("walk").count(), 6);
+    let mut data = Vec::new();
+    vfat.open_file("/DATA.BIN").expect("file").read_to_end(&mut data).expect("read");
+    assert_eq!(data.len(), 1300);
+    assert!(data.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+}
+
+#[test]
+fn test_dir_open_relative() {
+    use std::io::ErrorKind;
+
+    let vfat = MockImage::sample().mount();
+    let root = vfat.open_dir("/").expect("root");
+
+    let mut contents = String::new();
+    let mut file = root.open("sub directory/A long file name.txt")
+        .expect("nested relative path")
+        .into_file()
+        .expect("a file");
+    file.read_to_string(&mut contents).expect("read");
+    assert_eq!(contents, "nested\n");
+
+    let sub = root.open("Sub Directory").expect("subdirectory").into_dir().expect("a dir");
+    assert_eq!(sub.open("./SHORT.TXT").expect("file").name(), "SHORT.TXT");
+    assert_eq!(sub.open("SHORT.TXT/../SHORT.TXT").unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(root.open("Sub Directory/../HELLO.TXT").expect("file").name(), "HELLO.TXT");
+    assert_eq!(sub.open("").expect("itself").name(), "Sub Directory");
+
+    assert_eq!(sub.open("../HELLO.TXT").unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(root.open("Sub Directory/../../x").unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(root.open("/HELLO.TXT").unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(root.open("missing").unwrap_err().kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn test_probe() {
+    use vfat::{probe, FatType, PartitionTable};
+
+    let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+    let mut image = MockImage::new();
+    image.data[bpb + 71..bpb + 82].copy_from_slice(b"MY DISK    ");
+    let found = probe(Cursor::new(&mut image.data[..])).expect("probe");
+    assert_eq!(found.table, PartitionTable::Mbr);
+    assert!(found.volume.is_none());
+    assert_eq!(found.partitions.len(), 1);
+    let partition = &found.partitions[0];
+    assert_eq!((partition.index, partition.partition_type), (0, 0x0C));
+    assert_eq!(partition.start, MOCK_PARTITION_START as u64);
+    let fat = partition.fat.as_ref().expect("FAT partition");
+    assert_eq!(fat.fat_type, FatType::Fat32);
+    assert_eq!(fat.volume_label.as_ref().map(|s| s.as_str()), Some("MY DISK"));
+
+    // The same file system without the partition table.
+    let found = probe(Cursor::new(image.data[bpb..].to_vec())).expect("probe");
+    assert_eq!(found.table, PartitionTable::None);
+    assert!(found.partitions.is_empty());
+    assert_eq!(found.volume.expect("bare volume").fat_type, FatType::Fat32);
+
+    // A FAT16 volume: 512 root entries, 40-sector FATs, 4 sectors per cluster.
+    let mut fat16 = vec![0u8; 2 * MOCK_SECTOR_SIZE];
+    fat16[11..13].copy_from_slice(&u16_le(512));
+    fat16[13] = 4;
+    fat16[14..16].copy_from_slice(&u16_le(1));
+    fat16[16] = 2;
+    fat16[17..19].copy_from_slice(&u16_le(512));
+    fat16[22..24].copy_from_slice(&u16_le(40));
+    fat16[32..36].copy_from_slice(&u32_le(40000));
+    fat16[38] = 0x29;
+    fat16[43..54].copy_from_slice(b"SIXTEEN    ");
+    fat16[510..512].copy_from_slice(&[0x55, 0xAA]);
+    let volume = probe(Cursor::new(fat16)).expect("probe").volume.expect("bare volume");
+    assert_eq!(volume.fat_type, FatType::Fat16);
+    assert_eq!(volume.volume_label, Some("SIXTEEN".to_string()));
+
+    // A protective MBR in front of a GPT, and a partition past the device end.
+    let mut gpt = vec![0u8; 2 * MOCK_SECTOR_SIZE];
+    gpt[446 + 4] = 0xEE;
+    gpt[446 + 8..446 + 12].copy_from_slice(&u32_le(1));
+    gpt[446 + 16 + 4] = 0x0C;
+    gpt[446 + 16 + 8..446 + 16 + 12].copy_from_slice(&u32_le(100));
+    gpt[510..512].copy_from_slice(&[0x55, 0xAA]);
+    gpt[512..520].copy_from_slice(b"EFI PART");
+    let found = probe(Cursor::new(gpt)).expect("probe");
+    assert_eq!(found.table, PartitionTable::Gpt);
+    assert_eq!(found.partitions.len(), 2);
+    assert!(found.partitions.iter().all(|partition| partition.fat.is_none()));
+
+    let found = probe(Cursor::new(vec![0u8; MOCK_SECTOR_SIZE])).expect("probe");
+    assert_eq!(found.table, PartitionTable::None);
+    assert!(found.partitions.is_empty() && found.volume.is_none());
+}
+
+#[test]
+fn test_zero_cluster_dirs() {
+    let vfat = MockImage::sample().mount();
+    let up = vfat.open_dir("/Sub Directory")
+        .expect("subdirectory")
+        .entries()
+        .expect("entries")
+        .find(|entry| entry.name() == "..")
+        .expect("`..` entry")
+        .into_dir()
+        .expect("`..` is a directory");
+    let names: Vec<String> = up.entries().expect("root entries").map(|e| e.name().to_string()).collect();
+    assert!(names.iter().any(|name| name == "HELLO.TXT"));
+
+    let broken = || {
+        let mut image = MockImage::new();
+        image.add_dir(MOCK_ROOT, None, "BROKEN");
+        let slot = MockImage::root_slot_offset(0);
+        image.data[slot + 20..slot + 22].copy_from_slice(&[0, 0]);
+        image.data[slot + 26..slot + 28].copy_from_slice(&[0, 0]);
+        image
+    };
+    let vfat = broken().mount();
+    let dir = vfat.open_dir("/BROKEN").expect("directory");
+    assert_eq!(dir.entries().expect("lists as empty").count(), 0);
+
+    let mut config = VfatConfig::default();
+    config.strict_dirs = true;
+    let vfat = broken().mount_with(config);
+    let e = vfat.open_dir("/BROKEN").expect("directory").entries().err().expect("rejected");
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    // `..` to the root is fine even in strict mode.
+    let vfat = MockImage::sample().mount_with(VfatConfig { strict_dirs: true, ..VfatConfig::default() });
+    let up = vfat.open_dir("/Sub Directory").expect("subdirectory").entries().expect("entries")
+        .find(|entry| entry.name() == "..").expect("`..` entry").into_dir().expect("directory");
+    assert!(up.entries().is_ok());
+}
+
+#[test]
+fn test_find_entry_for_cluster() {
+    use std::path::PathBuf;
+
+    let vfat = MockImage::sample().mount();
+    let mut vfat = vfat.borrow_mut();
+    let owner = |vfat: &mut VFat, cluster: u32| vfat.find_entry_for_cluster(cluster.into()).expect("tree is readable");
+
+    assert_eq!(owner(&mut vfat, MOCK_ROOT), Some(PathBuf::from("/")));
+    assert_eq!(owner(&mut vfat, 3), Some(PathBuf::from("/HELLO.TXT")));
+    assert_eq!(owner(&mut vfat, 5), Some(PathBuf::from("/DATA.BIN")));
+    assert_eq!(owner(&mut vfat, 7), Some(PathBuf::from("/Sub Directory")));
+    assert_eq!(owner(&mut vfat, 8), Some(PathBuf::from("/Sub Directory/A long file name.txt")));
+    assert_eq!(owner(&mut vfat, 9), Some(PathBuf::from("/Sub Directory/SHORT.TXT")));
+    assert_eq!(owner(&mut vfat, 100), None);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_create_file() {
+    use std::io::ErrorKind;
+
+    let vfat = MockImage::sample().mount();
+    let names = |path: &str| -> Vec<(String, String)> {
+        vfat.open_dir(path).expect("dir").entries().expect("entries").map(|entry| {
+            let short_name = match entry {
+                ::vfat::Entry::File(ref file) => file.short_name(),
+                ::vfat::Entry::Dir(ref dir) => dir.short_name(),
+            };
+            (entry.name().to_string(), short_name)
+        }).collect()
+    };
+
+    let file = vfat.create_file("/NEW.TXT").expect("created");
+    assert_eq!(file.size(), 0);
+    vfat.create_file("/A much longer name.text").expect("created");
+    vfat.create_file("/A much longer name.other").expect("created");
+    vfat.create_file("/Sub Directory/lower.txt").expect("created");
+
+    let root = names("/");
+    assert_eq!(root.len(), 7);
+    assert_eq!(root[4], ("NEW.TXT".to_string(), "NEW.TXT".to_string()));
+    assert_eq!(root[5], ("A much longer name.text".to_string(), "AMUCHL~1.TEX".to_string()));
+    assert_eq!(root[6], ("A much longer name.other".to_string(), "AMUCHL~1.OTH".to_string()));
+    assert!(names("/Sub Directory").contains(&("lower.txt".to_string(), "LOWER.TXT".to_string())));
+
+    // Another name with the same 8.3 form gets the next number.
+    vfat.create_file("/A much longer name.texts").expect("created");
+    assert_eq!(names("/")[7].1, "AMUCHL~2.TEX");
+
+    assert_eq!(vfat.create_file("/NEW.TXT").unwrap_err().kind(), ErrorKind::AlreadyExists);
+    assert_eq!(vfat.open_file("/a much LONGER name.text").expect("reopened").size(), 0);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_file_write() {
+    let vfat = MockImage::sample().mount();
+    let data = (0..1500).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    let mut file = vfat.create_file("/WRITTEN.BIN").expect("created");
+    file.write_all(&data[..100]).expect("written");
+    file.write_all(&data[100..]).expect("written");
+    assert_eq!(file.size(), 1500);
+
+    // The size and chain are recorded in the directory entry.
+    let mut reopened = vfat.open_file("/WRITTEN.BIN").expect("reopened");
+    assert_eq!(reopened.size(), 1500);
+    let mut read = Vec::new();
+    reopened.read_to_end(&mut read).expect("readable");
+    assert_eq!(read, data);
+
+    // Writing in the middle overwrites in place without changing the size.
+    let mut hello = vfat.open_file("/HELLO.TXT").expect("file");
+    hello.seek(SeekFrom::Start(7)).expect("seekable");
+    hello.write_all(b"there").expect("written");
+    let mut read = String::new();
+    vfat.open_file("/HELLO.TXT").expect("file").read_to_string(&mut read).expect("readable");
+    assert_eq!(read, "Hello, there!\n");
+
+    // Writing past the end of a file grows it across clusters.
+    let mut data_bin = vfat.open_file("/DATA.BIN").expect("file");
+    data_bin.seek(SeekFrom::End(0)).expect("seekable");
+    data_bin.write_all(&[0xAB; 600]).expect("written");
+    let grown = vfat.open_file("/DATA.BIN").expect("file");
+    assert_eq!(grown.size(), 1900);
+    let length = vfat.borrow_mut().chain_length(4.into()).expect("chain");
+    assert_eq!(length as usize, (1900 + MOCK_SECTOR_SIZE - 1) / MOCK_SECTOR_SIZE);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_write_buffer() {
+    let mut config = VfatConfig::default();
+    config.write_buffer_size = Some(100);
+    let vfat = MockImage::sample().mount_with(config);
+    let data = (0..1500).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    let read = |path: &str| {
+        let mut read = Vec::new();
+        vfat.open_file(path).expect("file").read_to_end(&mut read).expect("readable");
+        read
+    };
+
+    let mut whole = vfat.create_file("/WHOLE.BIN").expect("created");
+    whole.write_all(&data).expect("written");
+
+    // Small writes to two files, interleaved, are held until the buffer
+    // fills, then written together. The size is recorded at once.
+    let mut first = vfat.create_file("/FIRST.BIN").expect("created");
+    let mut second = vfat.create_file("/SECOND.BIN").expect("created");
+    for chunk in data.chunks(7) {
+        first.write_all(chunk).expect("written");
+        second.write_all(chunk).expect("written");
+    }
+    assert_eq!(first.size(), 1500);
+    assert_eq!(vfat.open_file("/FIRST.BIN").expect("file").size(), 1500);
+    assert!(read("/FIRST.BIN") != data, "nothing was held");
+    first.flush().expect("flushed");
+    drop(second);
+    assert_eq!(read("/FIRST.BIN"), read("/WHOLE.BIN"));
+    assert_eq!(read("/SECOND.BIN"), data);
+
+    // A write elsewhere in the file writes the held bytes first, and reads
+    // see held bytes.
+    let mut file = vfat.open_file("/FIRST.BIN").expect("file");
+    file.write_all(b"abc").expect("written");
+    file.seek(SeekFrom::Start(10)).expect("seekable");
+    file.write_all(b"def").expect("written");
+    file.seek(SeekFrom::Start(0)).expect("seekable");
+    let mut start = [0u8; 13];
+    file.read_exact(&mut start).expect("readable");
+    assert_eq!(&start[..3], b"abc");
+    assert_eq!(&start[3..10], &data[3..10]);
+    assert_eq!(&start[10..], b"def");
+
+    // Clones see the original's held bytes, and don't write them again.
+    let mut file = vfat.open_file("/SECOND.BIN").expect("file");
+    file.write_all(b"xyz").expect("written");
+    let mut clone = file.clone();
+    clone.seek(SeekFrom::Start(0)).expect("seekable");
+    let mut start = [0u8; 3];
+    clone.read_exact(&mut start).expect("readable");
+    assert_eq!(&start, b"xyz");
+    let mut other = vfat.open_file("/SECOND.BIN").expect("file");
+    other.write_all(b"XYZ").expect("written");
+    drop(other);
+    drop(file);
+    drop(clone);
+    assert_eq!(&read("/SECOND.BIN")[..3], b"XYZ");
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_copy_rejects() {
+    use std::io::ErrorKind;
+
+    let vfat = MockImage::sample().mount();
+    assert_eq!(vfat.copy("/Sub Directory", "/COPY").unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(vfat.copy("/MISSING.TXT", "/COPY").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(vfat.copy("/HELLO.TXT", "/BAD?NAME").unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_copy() {
+    let vfat = MockImage::sample().mount();
+    assert_eq!(vfat.copy("/DATA.BIN", "/Sub Directory/Copy of data.bin").expect("copied"), 1300);
+
+    let mut original = vfat.open_file("/DATA.BIN").expect("original");
+    let mut copy = vfat.open_file("/Sub Directory/Copy of data.bin").expect("copy");
+    assert_eq!(copy.size(), 1300);
+    let (mut expected, mut actual) = (Vec::new(), Vec::new());
+    original.read_to_end(&mut expected).expect("readable");
+    copy.read_to_end(&mut actual).expect("readable");
+    assert_eq!(actual, expected);
+    // The original's chain is left alone.
+    assert_eq!(vfat.borrow_mut().chain_length(4.into()).expect("chain"), 3);
+    assert_eq!(vfat.borrow_mut().find_entry_for_cluster(10.into()).expect("tree is readable"),
+        Some("/Sub Directory/Copy of data.bin".into()));
+
+    // Empty files are copied without clusters.
+    assert_eq!(vfat.copy("/EMPTY", "/EMPTY2").expect("copied"), 0);
+    assert_eq!(vfat.open_file("/EMPTY2").expect("copy").size(), 0);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_create_case_insensitive_duplicate() {
+    use std::io::ErrorKind;
+
+    let mut image = MockImage::new();
+    image.add_file("README.TXT", b"read me\n");
+    image.add_file("NOTES.TXT", b"notes\n");
+    let vfat = image.mount();
+    assert_eq!(vfat.create_file("/Readme.txt").unwrap_err().kind(), ErrorKind::AlreadyExists);
+    assert_eq!(vfat.create_dir("/readme.TXT", false).unwrap_err().kind(), ErrorKind::AlreadyExists);
+    assert_eq!(vfat.rename("/NOTES.TXT", "/readme.txt").unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+    // Short names count too, and so do entries in subdirectories.
+    let vfat = MockImage::sample().mount();
+    assert_eq!(vfat.create_dir("/subdir~1", false).unwrap_err().kind(), ErrorKind::AlreadyExists);
+    let e = vfat.create_file("/Sub Directory/a LONG file NAME.txt").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+    let e = vfat.create_file("/HELLO.TXT/INSIDE.TXT").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+/// Returns the first cluster of the directory the `..` entry of the
+/// directory at `path` refers to.
+#[cfg(feature = "write")]
+fn dot_dot(vfat: &Shared<VFat>, path: &str) -> ::vfat::Cluster {
+    let entry = vfat.open_dir(path).expect("dir").entries().expect("entries")
+        .find(|entry| entry.name() == "..")
+        .expect("`..` entry");
+    entry.into_dir().expect("`..` is a directory").first_cluster()
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_create_dir() {
+    use std::io::ErrorKind;
+
+    let vfat = MockImage::sample().mount();
+    let dir = vfat.create_dir("/New Directory", false).expect("created");
+    assert!(dir.entries().expect("entries").map(|entry| entry.name().to_string()).eq(vec![".", ".."]));
+    vfat.create_file("/new directory/INSIDE.TXT").expect("created");
+    let names = vfat.open_dir("/New Directory").expect("dir").entries().expect("entries")
+        .map(|entry| entry.name().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![".", "..", "INSIDE.TXT"]);
+    assert_eq!(dot_dot(&vfat, "/New Directory"), vfat.open_dir("/").expect("root").first_cluster());
+
+    // Missing parents are created only if asked for.
+    let e = vfat.create_dir("/A/B/C", false).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    vfat.create_dir("/A/B/C", true).expect("created");
+    vfat.create_dir("/Sub Directory/D/E", true).expect("created");
+    assert_eq!(dot_dot(&vfat, "/A/B/C"), vfat.open_dir("/A/B").expect("dir").first_cluster());
+    assert!(vfat.open_dir("/Sub Directory/D/E").is_ok());
+    assert_eq!(vfat.create_dir("/A/B", true).unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+    // New entries are dated 1980-01-01.
+    let created = vfat.open_dir("/A").expect("dir").metadata.created();
+    assert_eq!((created.year(), created.month(), created.day()), (1980, 1, 1));
+
+    // A directory its parent has no room for leaves no cluster allocated.
+    let mut config = VfatConfig::default();
+    config.max_dir_entries = 5;
+    let vfat = MockImage::sample().mount_with(config);
+    let free = vfat.borrow_mut().free_extents().expect("FAT");
+    assert_eq!(vfat.create_dir("/FULL", false).unwrap_err().kind(), ErrorKind::Other);
+    assert_eq!(vfat.borrow_mut().free_extents().expect("FAT"), free);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_rename() {
+    use std::io::ErrorKind;
+
+    let vfat = MockImage::sample().mount();
+    let names = |path: &str| -> Vec<String> {
+        vfat.open_dir(path).expect("dir").entries().expect("entries")
+            .map(|entry| entry.name().to_string())
+            .collect()
+    };
+
+    // Within a directory, including a change of case only.
+    vfat.rename("/HELLO.TXT", "/Greeting.txt").expect("renamed");
+    vfat.rename("/Sub Directory/A long file name.txt", "/Sub Directory/A LONG file name.txt")
+        .expect("renamed");
+    assert_eq!(names("/"), vec!["DATA.BIN", "EMPTY", "Sub Directory", "Greeting.txt"]);
+    assert_eq!(names("/Sub Directory"), vec![".", "..", "SHORT.TXT", "A LONG file name.txt"]);
+    let mut read = String::new();
+    vfat.open_file("/greeting.txt").expect("file").read_to_string(&mut read).expect("readable");
+    assert_eq!(read, "Hello, world!\n");
+    assert_eq!(vfat.open("/HELLO.TXT").unwrap_err().kind(), ErrorKind::NotFound);
+
+    // Across directories: a moved directory's `..` follows it.
+    vfat.create_dir("/Outer", false).expect("created");
+    vfat.rename("/Sub Directory", "/Outer/Moved").expect("renamed");
+    assert_eq!(vfat.open_file("/Outer/Moved/SHORT.TXT").expect("file").size(), 6);
+    assert_eq!(dot_dot(&vfat, "/Outer/Moved"), vfat.open_dir("/Outer").expect("dir").first_cluster());
+    vfat.rename("/Outer/Moved", "/Back").expect("renamed");
+    assert_eq!(dot_dot(&vfat, "/Back"), vfat.open_dir("/").expect("root").first_cluster());
+
+    assert_eq!(vfat.rename("/Outer", "/Outer/Inner").unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(vfat.rename("/MISSING", "/OTHER").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(vfat.rename("/DATA.BIN", "/empty").unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+    // Removing is not supported yet, and isn't reported as read-only.
+    let e = vfat.remove("/Back", true).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Other);
+    assert!(!::vfat::is_read_only_error(&e));
+}
+
+#[test]
+fn test_dir_entry_at() {
+    let mut image = MockImage::sample();
+    image.data[MockImage::root_slot_offset(1)] = 0xE5;
+    let vfat = image.mount();
+    let root = vfat.open_dir("/").expect("root");
+
+    let hello = root.entry_at(0).expect("readable").expect("slot 0");
+    assert_eq!(&hello.bytes()[..11], b"HELLO   TXT");
+    assert!(hello.attributes().archive() && !hello.is_lfn());
+    assert!(root.entry_at(1).expect("readable").expect("slot 1").is_deleted());
+    let lfn = root.entry_at(3).expect("readable").expect("slot 3");
+    assert!(lfn.is_lfn());
+    assert_eq!(&root.entry_at(4).expect("readable").expect("slot 4").bytes()[..11], b"SUBDIR~1   ");
+    assert!(root.entry_at(5).expect("readable").expect("slot 5").is_end());
+
+    // The root is a single cluster of 16 slots.
+    let slots = MOCK_SECTOR_SIZE / 32;
+    assert!(root.entry_at(slots - 1).expect("readable").is_some());
+    assert!(root.entry_at(slots).expect("readable").is_none());
+}
+
+#[test]
+fn test_volume_id_directory_not_traversed() {
+    use std::io::ErrorKind;
+
+    let mut image = MockImage::new();
+    let fake = image.add_dir(MOCK_ROOT, None, "FAKEDIR");
+    image.add_file_in(fake, None, "INSIDE.TXT", b"inside\n");
+    image.data[MockImage::root_slot_offset(0) + 11] = 0x18;
+    let vfat = image.mount();
+
+    // The entry itself can still be looked up; only descending is refused.
+    assert!(vfat.open("/FAKEDIR").expect("opened").metadata().volume_id());
+    assert!(vfat.open_dir("/").expect("root").find("FAKEDIR").is_ok());
+    assert_eq!(vfat.open("/FAKEDIR/INSIDE.TXT").unwrap_err().kind(), ErrorKind::InvalidData);
+    let root = vfat.open_dir("/").expect("root");
+    assert_eq!(root.open("fakedir/INSIDE.TXT").unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "write")]
+#[test]
+fn test_add_dir_entries_grows_chain() {
+    use vfat::Cluster;
+
+    let entry = |name: &str| {
+        let mut entry = [0u8; 32];
+        entry[..11].copy_from_slice(&short_name(name));
+        entry[11] = 0x20;
+        entry
+    };
+    let slots = MOCK_SECTOR_SIZE / 32;
+
+    // The root's only cluster is exactly full: the new entry starts a second
+    // one, followed by an end marker.
+    let mut image = MockImage::new();
+    for i in 0..slots {
+        image.add_file(&format!("F{}", i), &[]);
+    }
+    let vfat = image.mount();
+    let root = Cluster::from(MOCK_ROOT);
+    let slot = vfat.borrow_mut().add_dir_entries(root, &[entry("NEW.TXT")]).expect("added");
+    assert_eq!(slot, slots);
+    assert_eq!(vfat.borrow_mut().chain_length(root).expect("chain"), 2);
+    let dir = vfat.open_dir("/").expect("root");
+    assert_eq!(&dir.entry_at(slots).expect("readable").expect("slot").bytes()[..11], b"NEW     TXT");
+    assert!(dir.entry_at(slots + 1).expect("readable").expect("slot").is_end());
+    assert_eq!(dir.entries().expect("entries").count(), slots + 1);
+
+    // A run starting at the last free slot spills into a new cluster, and a
+    // deleted slot is reused before the end of the directory.
+    let mut image = MockImage::new();
+    for i in 0..slots - 1 {
+        image.add_file(&format!("F{}", i), &[]);
+    }
+    image.data[MockImage::root_slot_offset(2)] = 0xE5;
+    let vfat = image.mount();
+    let entries = [entry("A"), entry("B"), entry("C")];
+    let slot = vfat.borrow_mut().add_dir_entries(root, &entries).expect("added");
+    assert_eq!(slot, slots - 1);
+    assert_eq!(vfat.borrow_mut().add_dir_entries(root, &[entry("D")]).expect("added"), 2);
+    let dir = vfat.open_dir("/").expect("root");
+    assert!(dir.entry_at(slots + 2).expect("readable").expect("slot").is_end());
+    let names: Vec<String> = dir.entries().expect("entries").map(|e| e.name().to_string()).collect();
+    assert_eq!(names.len(), slots + 2);
+    assert_eq!(&names[slots - 1..], &["A", "B", "C"]);
+    assert_eq!(names[2], "D");
+}
+
+#[test]
+fn test_raw_name_bytes() {
+    let mut image = MockImage::new();
+    image.add_file("HELLO.TXT", b"hello");
+    image.add_lfn_file("Long name", "LONGNA~1", b"long");
+
+    // Off by default.
+    let vfat = MockImage::sample().mount();
+    let file = vfat.open_file("/HELLO.TXT").expect("opens");
+    assert!(file.raw_name_bytes().is_empty());
+
+    let vfat = image.mount_with(VfatConfig { keep_raw_names: true, ..VfatConfig::default() });
+    let file = vfat.open_file("/HELLO.TXT").expect("opens");
+    assert_eq!(file.raw_name_bytes(), b"HELLO   TXT");
+
+    let file = vfat.open_file("/Long name").expect("opens");
+    let raw = file.raw_name_bytes();
+    assert_eq!(&raw[..11], b"LONGNA~1   ");
+    // One LFN entry: 9 characters, a NUL terminator and 0xFFFF padding.
+    assert_eq!(raw.len(), 11 + 26);
+    let units: Vec<u16> = raw[11..].chunks(2).map(|c| c[0] as u16 | (c[1] as u16) << 8).collect();
+    assert_eq!(String::from_utf16_lossy(&units[..9]), "Long name");
+    assert_eq!(&units[9..], &[0x0000, 0xFFFF, 0xFFFF, 0xFFFF]);
+
+    let root = vfat.open_dir("/").expect("root");
+    assert!(root.raw_name_bytes().is_empty());
+}
+
+#[test]
+fn test_file_sectors() {
+    let vfat = MockImage::sample().mount();
+
+    let file = vfat.open_file("/DATA.BIN").expect("opens");
+    let sectors: Vec<(u64, Vec<u8>)> = file.sectors().collect::<::std::io::Result<_>>().expect("readable");
+    let numbers: Vec<u64> = sectors.iter().map(|&(sector, _)| sector).collect();
+    let first = MOCK_DATA_START as u64 + 2;
+    assert_eq!(numbers, vec![first, first + 1, first + 2]);
+    assert_eq!(sectors[2].1.len(), 1300 - 2 * MOCK_SECTOR_SIZE);
+    let contents: Vec<u8> = sectors.into_iter().flat_map(|(_, data)| data).collect();
+    assert_eq!(contents, (0..1300u32).map(|i| i as u8).collect::<Vec<u8>>());
+
+    assert_eq!(vfat.open_file("/EMPTY").expect("opens").sectors().count(), 0);
+
+    // `DATA.BIN`'s chain cut short after its first cluster.
+    let mut image = MockImage::sample();
+    image.set_fat(4, 0x0FFFFFFF);
+    let vfat = image.mount();
+    let mut sectors = vfat.open_file("/DATA.BIN").expect("opens").sectors();
+    assert!(sectors.next().expect("first sector").is_ok());
+    let e = sectors.next().expect("error").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::UnexpectedEof);
+    assert!(sectors.next().is_none());
+}
+
+#[test]
+fn test_read_dir_raw() {
+    use vfat::DirEntryParser;
+
+    let mut image = MockImage::sample();
+    image.data[MockImage::root_slot_offset(1)] = 0xE5;
+    let expected_root = image.data[MockImage::root_slot_offset(0)..MockImage::root_slot_offset(16)].to_vec();
+    let expected_sub = image.data[MockImage::slot_offset(7, 0)..MockImage::slot_offset(7, 16)].to_vec();
+    let vfat = image.mount();
+    let mut vfat = vfat.borrow_mut();
+
+    let root = vfat.read_root_raw().expect("readable");
+    assert_eq!(root, expected_root);
+    assert_eq!(root[32], 0xE5);
+    let names: Vec<String> = DirEntryParser::from_bytes(&root).map(|e| e.name).collect();
+    assert_eq!(names, vec!["HELLO.TXT", "EMPTY", "Sub Directory"]);
+
+    assert_eq!(vfat.read_dir_raw(7.into()).expect("readable"), expected_sub);
+}
+
+#[test]
+fn test_timestamp_utc_offset() {
+    use std::time::{Duration, UNIX_EPOCH};
+    use vfat::{Date, Time, Timestamp};
+
+    // 2018-03-14 09:26:54 local time.
+    let date = Date::from(((2018 - 1980) << 9) | (3 << 5) | 14);
+    let time = Time::from((9 << 11) | (26 << 5) | (54 / 2));
+    let timestamp = Timestamp::from((date, time));
+    let utc = UNIX_EPOCH + Duration::from_secs(1521019614);
+
+    assert_eq!(timestamp.to_system_time(), Some(utc));
+    assert_eq!(timestamp.to_system_time_with_offset(0), Some(utc));
+    // JST is UTC+9, so the same wall-clock time happened 9 hours earlier.
+    assert_eq!(timestamp.to_system_time_with_offset(540), Some(utc - Duration::from_secs(9 * 3600)));
+    assert_eq!(timestamp.to_system_time_with_offset(-300), Some(utc + Duration::from_secs(5 * 3600)));
+
+    let invalid = Timestamp::from((Date::from(0), Time::from(0)));
+    assert_eq!(invalid.to_system_time_with_offset(540), None);
+
+    // Days past the end of the month are rejected rather than rolled over,
+    // taking leap years into account.
+    let on = |year: u16, month: u16, day: u16| {
+        Timestamp::from((Date::from(((year - 1980) << 9) | (month << 5) | day), Time::from(0)))
+            .to_system_time()
+    };
+    assert_eq!(on(2018, 4, 31), None);
+    assert_eq!(on(2018, 2, 29), None);
+    assert_eq!(on(2100, 2, 29), None);
+    assert_eq!(on(2020, 2, 30), None);
+    assert_eq!(on(2020, 2, 29), Some(UNIX_EPOCH + Duration::from_secs(1582934400)));
+    assert_eq!(on(2000, 2, 29), Some(UNIX_EPOCH + Duration::from_secs(951782400)));
+    assert!(on(2018, 12, 31).is_some());
+}
+
+#[test]
+fn test_locate_entry() {
+    use std::io::ErrorKind;
+    use vfat::{Cluster, EntryLocation};
+
+    let mut image = MockImage::sample();
+    // A deleted slot ahead of the sought entry in the root.
+    image.data[MockImage::root_slot_offset(1)] = 0xE5;
+    let data = image.data.clone();
+    let vfat = image.mount();
+    let slot_name = |location: EntryLocation| {
+        let offset = MockImage::cluster_offset(location.cluster.inner()) + location.offset;
+        data[offset..offset + 11].to_vec()
+    };
+
+    let location = vfat.locate_entry("/HELLO.TXT").expect("located");
+    assert_eq!(location, EntryLocation { cluster: Cluster::from(MOCK_ROOT), offset: 0 });
+    assert_eq!(vfat.locate_entry("/empty").expect("located").offset, 2 * 32);
+    // The 8.3 slot, after the LFN slots, is returned.
+    let location = vfat.locate_entry("/Sub Directory").expect("located");
+    assert_eq!(location.offset, 4 * 32);
+    assert_eq!(slot_name(location), b"SUBDIR~1   ");
+    let location = vfat.locate_entry("/Sub Directory/A long file name.txt").expect("located");
+    assert_eq!(location.cluster, Cluster::from(7));
+    assert_eq!(slot_name(location), b"ALONGF~1TXT");
+    assert_eq!(slot_name(vfat.locate_entry("/Sub Directory/short.txt").expect("located")), b"SHORT   TXT");
+
+    assert_eq!(vfat.locate_entry("/DATA.BIN").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(vfat.locate_entry("/").unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_fat_entry_display() {
+    use vfat::FatEntry;
+
+    assert_eq!(FatEntry(0x0FFFFFFF).to_string(), "0x0FFFFFFF (Eoc(268435455))");
+    // The reserved upper bits are masked off, as for `status`.
+    assert_eq!(FatEntry(0xF0000000).to_string(), "0x00000000 (Free)");
+    assert_eq!(FatEntry(0xA0000005).value(), 5);
+    assert_eq!(format!("{}", FatEntry(0x0FFFFFF7)), "0x0FFFFFF7 (Bad)");
+    assert!(FatEntry(0x10000123).to_string().starts_with("0x00000123 (Data("));
+}
+
+#[test]
+fn test_read_nth_cluster() {
+    use std::io::ErrorKind;
+
+    let mut image = MockImage::new();
+    let data: Vec<u8> = (0..1300u32).map(|i| (i / 512) as u8 + 1).collect();
+    image.add_file("FRAG.BIN", &data);
+    // Reorder the chain to 3, 5, 4 (moving the data along), so that cluster
+    // indices and cluster numbers disagree.
+    image.set_fat(3, 5);
+    image.set_fat(5, 4);
+    image.set_fat(4, 0x0FFFFFFF);
+    let (second, third) = (MockImage::cluster_offset(4), MockImage::cluster_offset(5));
+    let mut moved = image.data.clone();
+    moved[third..third + 512].copy_from_slice(&image.data[second..second + 512]);
+    moved[second..second + 512].copy_from_slice(&image.data[third..third + 512]);
+    let vfat = VFat::from(MockDevice::new(moved)).expect("mock image mounts");
+
+    let mut file = vfat.open_file("/FRAG.BIN").expect("opens");
+    let mut buf = [0u8; 512];
+    assert_eq!(file.read_nth_cluster(0, &mut buf).expect("cluster 0"), 512);
+    assert!(buf.iter().all(|&b| b == 1));
+    assert_eq!(file.read_nth_cluster(1, &mut buf).expect("cluster 1"), 512);
+    assert!(buf.iter().all(|&b| b == 2));
+    assert_eq!(file.read_nth_cluster(2, &mut buf).expect("cluster 2"), 1300 - 1024);
+    assert!(buf[..1300 - 1024].iter().all(|&b| b == 3));
+    let e = file.read_nth_cluster(3, &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert_eq!(file.stream_position(), 0);
+
+    // Reading as a stream follows the chain too.
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents).expect("readable");
+    assert_eq!(contents, data);
+}
+
+#[test]
+fn test_subdirectories() {
+    let mut image = MockImage::new();
+    image.add_file("A.TXT", b"a");
+    let first = image.add_dir(MOCK_ROOT, Some("First directory"), "FIRSTD~1");
+    image.add_file("B.TXT", b"b");
+    image.add_dir(MOCK_ROOT, None, "SECOND");
+    // A volume label that also claims to be a directory.
+    image.add_dir(MOCK_ROOT, None, "LABEL");
+    image.data[MockImage::root_slot_offset(6) + 11] = 0x18;
+    image.add_dir(first, None, "NESTED");
+    let vfat = image.mount();
+
+    let names = |dirs: Vec<::vfat::Dir>| dirs.into_iter().map(|dir| dir.name).collect::<Vec<_>>();
+    let root = vfat.open_dir("/").expect("root");
+    assert_eq!(names(root.subdirectories().expect("listable")), vec!["First directory", "SECOND"]);
+    let first = vfat.open_dir("/First directory").expect("opens");
+    assert_eq!(names(first.subdirectories().expect("listable")), vec!["NESTED"]);
+    let nested = vfat.open_dir("/First directory/NESTED").expect("opens");
+    assert!(nested.subdirectories().expect("listable").is_empty());
+}
+
+/// Records the sectors read from the device it wraps.
+struct RecordingDevice(MockDevice, ::std::sync::Arc<::std::sync::Mutex<Vec<u64>>>);
+
+impl BlockDevice for RecordingDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.1.lock().unwrap().push(n);
+        self.0.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.0.write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_entries_streaming() {
+    use std::sync::{Arc, Mutex};
+
+    // A root of two clusters: 16 files in the first, one in the second.
+    let mut image = MockImage::new();
+    for i in 0..MOCK_SECTOR_SIZE / 32 {
+        image.add_file(&format!("F{}", i), &[]);
+    }
+    let second = 50;
+    image.set_fat(MOCK_ROOT, second);
+    image.set_fat(second, 0x0FFFFFFF);
+    let first_slot = image.data[MockImage::root_slot_offset(0)..MockImage::root_slot_offset(1)].to_vec();
+    let late = image.cluster_mut(second);
+    late[..32].copy_from_slice(&first_slot);
+    late[..11].copy_from_slice(b"LATE    TXT");
+
+    let reads = Arc::new(Mutex::new(Vec::new()));
+    let device = RecordingDevice(MockDevice::new(image.data), reads.clone());
+    let vfat = VFat::from(device).expect("mock image mounts");
+    let late_sector = MOCK_DATA_START as u64 + second as u64 - 2;
+    let read_late = || reads.lock().unwrap().contains(&late_sector);
+
+    let root = vfat.open_dir("/").expect("root");
+    assert_eq!(root.find("F3").expect("found").name(), "F3");
+    assert!(!read_late(), "lookup read past the entry found");
+    let first: Vec<String> = root.entries_streaming()
+        .expect("listable")
+        .take(16)
+        .map(|entry| entry.expect("readable").name().to_string())
+        .collect();
+    assert_eq!(first.len(), 16);
+    assert!(!read_late());
+
+    let all: Vec<String> = root.entries_streaming()
+        .expect("listable")
+        .map(|entry| entry.expect("readable").name().to_string())
+        .collect();
+    assert!(read_late());
+    assert_eq!(all.len(), 17);
+    assert_eq!(all[16], "LATE.TXT");
+    let buffered: Vec<String> = root.entries().expect("listable").map(|e| e.name().to_string()).collect();
+    assert_eq!(all, buffered);
+}
+
+#[test]
+fn test_entries_streaming_errors() {
+    let mut image = MockImage::sample();
+    // The subdirectory's cluster has no end marker and its chain continues
+    // into a free cluster.
+    for slot in 6..MOCK_SECTOR_SIZE / 32 {
+        image.data[MockImage::slot_offset(7, slot)] = 0xE5;
+    }
+    image.set_fat(7, 60);
+    let vfat = image.mount();
+    let sub = vfat.open_dir("/Sub Directory").expect("opens");
+
+    let mut entries = sub.entries_streaming().expect("listable");
+    let names: Vec<String> = entries.by_ref().take(4).map(|e| e.expect("readable").name().to_string()).collect();
+    assert_eq!(names, vec![".", "..", "A long file name.txt", "SHORT.TXT"]);
+    let e = entries.next().expect("error").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn test_max_depth() {
+    use std::io::ErrorKind;
+    use vfat::Cluster;
+
+    let mut image = MockImage::new();
+    let sub = image.add_dir(MOCK_ROOT, None, "SUB");
+    image.add_file("A.TXT", b"a");
+    // An entry in `SUB` referring back to the root, as a corrupt `..` might.
+    image.add_dir(sub, None, "LOOP");
+    let entry = MockImage::slot_offset(sub, 2);
+    image.data[entry + 26..entry + 28].copy_from_slice(&u16_le(MOCK_ROOT as u16));
+    let data = image.data;
+
+    let walked = |vfat: &::vfat::Shared<VFat>| -> Vec<_> {
+        vfat.walk("/")
+            .expect("walk")
+            .map(|r| r.map(|e| (e.path.to_string_lossy().into_owned(), e.depth)).map_err(|e| e.kind()))
+            .collect()
+    };
+
+    // The loop is reported as soon as it leads back to the root, well before
+    // the depth limit.
+    let vfat = VFat::from(MockDevice::new(data.clone())).expect("mock image mounts");
+    assert_eq!(walked(&vfat), vec![
+        Ok(("/SUB".to_string(), 1)),
+        Ok(("/SUB/LOOP".to_string(), 2)),
+        Err(ErrorKind::InvalidData),
+        Ok(("/A.TXT".to_string(), 1)),
+    ]);
+
+    // Without a loop, nesting deeper than the limit is refused.
+    let mut deep = MockImage::new();
+    let sub = deep.add_dir(MOCK_ROOT, None, "SUB");
+    let inner = deep.add_dir(sub, None, "INNER");
+    deep.add_file_in(inner, None, "DEEP.TXT", b"deep");
+    let config = VfatConfig { max_depth: 2, ..VfatConfig::default() };
+    assert_eq!(walked(&deep.mount_with(config)), vec![
+        Ok(("/SUB".to_string(), 1)),
+        Ok(("/SUB/INNER".to_string(), 2)),
+        Err(ErrorKind::InvalidData),
+    ]);
+
+    // The other traversals stop at the loop, but still honour the limit.
+    let config = VfatConfig { max_depth: 1, ..VfatConfig::default() };
+    let vfat = VFat::from_with_config(MockDevice::new(data), config).expect("mock image mounts");
+    let e = vfat.borrow_mut().find_entry_for_cluster(Cluster::from(60)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    let e = vfat.borrow_mut().warm_cache_for_tree(Path::new("/")).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    let vfat = MockImage::sample().mount();
+    assert_eq!(vfat.borrow_mut().find_entry_for_cluster(Cluster::from(60)).expect("searchable"), None);
+}
+
+#[test]
+fn test_find_by_extension() {
+    let mut image = MockImage::new();
+    image.add_file("A.LOG", b"a");
+    image.add_lfn_file("archive.tar.log", "ARCHIV~1.LOG", b"b");
+    image.add_lfn_file("notes.log.txt", "NOTESL~1.TXT", b"c");
+    image.add_file("LOG", b"d");
+    image.add_lfn_file(".log", "LOG~1", b"e");
+    let dir = image.add_dir(MOCK_ROOT, Some("old.log"), "OLDLOG~1.LOG");
+    image.add_file_in(dir, Some("Nested.Log"), "NESTED.LOG", b"f");
+    let vfat = image.mount();
+
+    let found = vfat.find_by_extension("/", "log").expect("walkable");
+    let found: Vec<String> = found.iter().map(|path| path.to_string_lossy().into_owned()).collect();
+    assert_eq!(found, vec!["/A.LOG", "/archive.tar.log", "/old.log/Nested.Log"]);
+    assert_eq!(vfat.find_by_extension("/old.log", "LOG").expect("walkable").len(), 1);
+    assert!(vfat.find_by_extension("/", "").expect("walkable").is_empty());
+}
+
+#[cfg(not(feature = "write"))]
+#[test]
+fn test_read_only_errors() {
+    use std::io::ErrorKind;
+    use vfat::is_read_only_error;
+
+    let vfat = MockImage::sample().mount();
+    let check = |result: ::std::io::Result<()>| {
+        let e = result.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert!(is_read_only_error(&e), "{}", e);
+    };
+    check(vfat.create_file("/NEW.TXT").map(|_| ()));
+    check(vfat.create_dir("/NEWDIR", false).map(|_| ()));
+    check(vfat.rename("/HELLO.TXT", "/BYE.TXT"));
+    check(vfat.remove("/HELLO.TXT", false));
+    // Even before the name is looked at.
+    check(vfat.create_file("/a*b.txt").map(|_| ()));
+    check(vfat.create_dir("/dir?", false).map(|_| ()));
+    check(vfat.rename("/MISSING", "/x|y"));
+
+    let mut file = vfat.open_file("/HELLO.TXT").expect("file exists");
+    check(file.write(b"x").map(|_| ()));
+    check(file.flush());
+    check(file.sync());
+
+    // Other errors are not mistaken for it.
+    let e = vfat.open_file("/MISSING.TXT").unwrap_err();
+    assert!(!is_read_only_error(&e));
+}
+
+#[test]
+fn test_path_of() {
+    use std::path::PathBuf;
+
+    let vfat = MockImage::sample().mount();
+    let file = vfat.open_file("/Sub Directory/A long file name.txt").expect("file exists");
+    let dir = vfat.open_dir("/Sub Directory").expect("dir exists");
+    let mut vfat = vfat.borrow_mut();
+    let nested = PathBuf::from("/Sub Directory/A long file name.txt");
+    assert_eq!(vfat.path_of(file.first_cluster()).expect("searchable"), Some(nested));
+    assert_eq!(vfat.path_of(dir.first_cluster()).expect("searchable"), Some(PathBuf::from("/Sub Directory")));
+    assert_eq!(vfat.path_of(MOCK_ROOT.into()).expect("searchable"), Some(PathBuf::from("/")));
+    // Unlike `find_entry_for_cluster`, only first clusters match.
+    assert_eq!(vfat.path_of(5.into()).expect("searchable"), None);
+    assert_eq!(vfat.find_entry_for_cluster(5.into()).expect("searchable"), Some(PathBuf::from("/DATA.BIN")));
+}
+
+#[test]
+fn test_max_file_size() {
+    use std::io::ErrorKind;
+
+    // DATA.BIN is 1300 bytes in three 512-byte clusters.
+    let config = VfatConfig { max_file_size: Some(1000), ..VfatConfig::default() };
+    let vfat = MockImage::sample().mount_with(config);
+    assert_eq!(vfat.open_file("/DATA.BIN").unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(vfat.open_file("/HELLO.TXT").is_ok());
+    let mut buf = Vec::new();
+    let e = vfat.borrow_mut().read_chain(4.into(), &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    // Allocation stops at the limit rounded up to whole clusters.
+    assert_eq!(buf.len(), 1024);
+
+    // Files listed rather than opened are caught when read.
+    let mut file = vfat.open_dir("/").expect("root").entries().expect("listable")
+        .find(|entry| entry.name() == "DATA.BIN").expect("listed").into_file().expect("file");
+    assert_eq!(file.read_all_into(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+
+    let config = VfatConfig { max_file_size: Some(1300), ..VfatConfig::default() };
+    let vfat = MockImage::sample().mount_with(config);
+    let mut file = vfat.open_file("/DATA.BIN").expect("within the limit");
+    assert_eq!(file.read_all_into(&mut buf).expect("readable"), 1300);
+    assert_eq!(vfat.borrow_mut().read_chain(4.into(), &mut buf).expect("readable"), 1536);
+
+    // Directories larger than the limit still list.
+    let config = VfatConfig { max_file_size: Some(10), ..VfatConfig::default() };
+    let vfat = MockImage::sample().mount_with(config);
+    assert_eq!(vfat.open_dir("/Sub Directory").expect("dir").entries().expect("listable").count(), 4);
+    assert_eq!(vfat.open_file("/Sub Directory/SHORT.TXT").expect("within the limit").size(), 6);
+}
+
+#[test]
+fn test_fat_type_checked_at_mount() {
+    use vfat::FatType;
+
+    let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+
+    // The mock image has just enough clusters for FAT32.
+    let mut image = MockImage::new();
+    let parsed = BiosParameterBlock::from(Cursor::new(&mut image.data[..]), MOCK_PARTITION_START as u64)
+        .expect("valid BPB");
+    assert_eq!(parsed.fat_type(), Some(FatType::Fat32));
+    image.mount();
+
+    // Shrinking the volume below 65525 clusters makes it FAT16, even with
+    // the FAT32 fields in place.
+    let sectors = (MOCK_DATA_START - MOCK_PARTITION_START + 65524) as u32;
+    let mut image = MockImage::new();
+    image.data[bpb + 32..bpb + 36].copy_from_slice(&u32_le(sectors));
+    let e = VFat::from(MockDevice::new(image.data)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb("Not a FAT32 volume: detected FAT16."));
+
+    // One cluster more is FAT32 again.
+    let mut image = MockImage::new();
+    image.data[bpb + 32..bpb + 36].copy_from_slice(&u32_le(sectors + 1));
+    VFat::from(MockDevice::new(image.data)).expect("FAT32 volume mounts");
+
+    // 4084 clusters is the most FAT12 holds.
+    let mut image = MockImage::new();
+    image.data[bpb + 32..bpb + 36].copy_from_slice(&u32_le(sectors - (65524 - 4084)));
+    let e = VFat::from(MockDevice::new(image.data)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb("Not a FAT32 volume: detected FAT12."));
+}
@@ -0,0 +1,399 @@
+//! Builds FAT32 volumes in memory so that tests don't depend on the
+//! assignment's resource images.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+#[cfg(feature = "write")]
+use std::sync::{Arc, Mutex};
+
+use vfat::{sfn_checksum, Shared, VFat, VfatConfig};
+use traits::BlockDevice;
+
+/// Geometry of the images built by `MockImage`: 512-byte sectors, one sector
+/// per cluster, and enough clusters for the volume to be a genuine FAT32 one.
+pub const MOCK_SECTOR_SIZE: usize = 512;
+pub const MOCK_PARTITION_START: usize = 8;
+pub const MOCK_RESERVED_SECTORS: usize = 32;
+pub const MOCK_CLUSTERS: usize = 65600;
+pub const MOCK_SECTORS_PER_FAT: usize =
+    ((MOCK_CLUSTERS + 2) * 4 + MOCK_SECTOR_SIZE - 1) / MOCK_SECTOR_SIZE;
+pub const MOCK_DATA_START: usize =
+    MOCK_PARTITION_START + MOCK_RESERVED_SECTORS + 2 * MOCK_SECTORS_PER_FAT;
+/// The cluster of the root directory.
+pub const MOCK_ROOT: u32 = 2;
+/// The size in bytes of the whole image, MBR included.
+pub const MOCK_IMAGE_SIZE: usize = (MOCK_DATA_START + MOCK_CLUSTERS) * MOCK_SECTOR_SIZE;
+
+/// Builds a small FAT32 image in memory: an MBR with one partition, an EBPB,
+/// an FSInfo sector, two FATs and a single-cluster root directory at cluster
+/// 2. Files and directories are allocated contiguously after it; every
+/// directory occupies a single cluster.
+pub struct MockImage {
+    /// The image up to the end of the last cluster allocated so far. The
+    /// clusters after it are all zeros, so they are left out to keep images
+    /// small; mount `data` through a `MockDevice`, which reads them back.
+    pub data: Vec<u8>,
+    next_cluster: u32,
+    /// The number of used entries of each directory, keyed by its cluster.
+    dir_slots: HashMap<u32, usize>,
+}
+
+impl MockImage {
+    pub fn new() -> MockImage {
+        fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        let sectors = MOCK_DATA_START - MOCK_PARTITION_START + MOCK_CLUSTERS;
+        let mut data = vec![0u8; MockImage::cluster_offset(MOCK_ROOT + 1)];
+
+        // MBR: one FAT32 (LBA) partition.
+        data[446 + 4] = 0x0C;
+        put(&mut data, 446 + 8, &u32_le(MOCK_PARTITION_START as u32));
+        put(&mut data, 446 + 12, &u32_le(sectors as u32));
+        put(&mut data, 510, &[0x55, 0xAA]);
+
+        // EBPB.
+        let bpb = MOCK_PARTITION_START * MOCK_SECTOR_SIZE;
+        put(&mut data, bpb, &[0xEB, 0x58, 0x90]);
+        put(&mut data, bpb + 3, b"MSWIN4.1");
+        put(&mut data, bpb + 11, &u16_le(MOCK_SECTOR_SIZE as u16));
+        data[bpb + 13] = 1;
+        put(&mut data, bpb + 14, &u16_le(MOCK_RESERVED_SECTORS as u16));
+        data[bpb + 16] = 2;
+        data[bpb + 21] = 0xF8;
+        put(&mut data, bpb + 28, &u32_le(MOCK_PARTITION_START as u32));
+        put(&mut data, bpb + 32, &u32_le(sectors as u32));
+        put(&mut data, bpb + 36, &u32_le(MOCK_SECTORS_PER_FAT as u32));
+        put(&mut data, bpb + 44, &u32_le(MOCK_ROOT));
+        put(&mut data, bpb + 48, &u16_le(1));
+        put(&mut data, bpb + 50, &u16_le(6));
+        data[bpb + 64] = 0x80;
+        data[bpb + 66] = 0x29;
+        put(&mut data, bpb + 71, b"NO NAME    ");
+        put(&mut data, bpb + 82, b"FAT32   ");
+        put(&mut data, bpb + 510, &[0x55, 0xAA]);
+
+        // FSInfo, in the sector after the EBPB.
+        let fsinfo = bpb + MOCK_SECTOR_SIZE;
+        put(&mut data, fsinfo, &u32_le(0x41615252));
+        put(&mut data, fsinfo + 484, &u32_le(0x61417272));
+        put(&mut data, fsinfo + 508, &u32_le(0xAA550000));
+
+        let mut dir_slots = HashMap::new();
+        dir_slots.insert(MOCK_ROOT, 0);
+        let mut image = MockImage {
+            data,
+            next_cluster: MOCK_ROOT + 1,
+            dir_slots,
+        };
+        image.set_fat(0, 0x0FFFFFF8);
+        image.set_fat(1, 0x0FFFFFFF);
+        image.set_fat(MOCK_ROOT, 0x0FFFFFFF);
+        image.set_fsinfo(MOCK_CLUSTERS as u32 - 1, MOCK_ROOT + 1);
+        image
+    }
+
+    /// An image holding a few files in the root directory and a subdirectory,
+    /// with long file names on some of the entries:
+    ///
+    /// ```text
+    /// /HELLO.TXT                        "Hello, world!\n"
+    /// /DATA.BIN                         1300 bytes, byte i is i % 256
+    /// /EMPTY                            empty
+    /// /Sub Directory/                   (SUBDIR~1)
+    /// /Sub Directory/A long file name.txt  (ALONGF~1.TXT) "nested\n"
+    /// /Sub Directory/SHORT.TXT          "short\n"
+    /// ```
+    pub fn sample() -> MockImage {
+        let mut image = MockImage::new();
+        image.add_file("HELLO.TXT", b"Hello, world!\n");
+        let data: Vec<u8> = (0..1300u32).map(|i| i as u8).collect();
+        image.add_file("DATA.BIN", &data);
+        image.add_file("EMPTY", &[]);
+        let sub = image.add_dir(MOCK_ROOT, Some("Sub Directory"), "SUBDIR~1");
+        image.add_file_in(sub, Some("A long file name.txt"), "ALONGF~1.TXT", b"nested\n");
+        image.add_file_in(sub, None, "SHORT.TXT", b"short\n");
+        image
+    }
+
+    /// Byte offset of cluster `cluster` in the image.
+    pub fn cluster_offset(cluster: u32) -> usize {
+        (MOCK_DATA_START + cluster as usize - 2) * MOCK_SECTOR_SIZE
+    }
+
+    /// Byte offset of the `slot`th 32-byte entry of the root directory.
+    pub fn root_slot_offset(slot: usize) -> usize {
+        MockImage::slot_offset(MOCK_ROOT, slot)
+    }
+
+    /// Byte offset of the `slot`th 32-byte entry of the directory at `dir`.
+    pub fn slot_offset(dir: u32, slot: usize) -> usize {
+        MockImage::cluster_offset(dir) + slot * 32
+    }
+
+    /// The bytes of cluster `cluster`, which need not be allocated.
+    pub fn cluster_mut(&mut self, cluster: u32) -> &mut [u8] {
+        let offset = MockImage::cluster_offset(cluster);
+        ensure_len(&mut self.data, offset + MOCK_SECTOR_SIZE);
+        &mut self.data[offset..offset + MOCK_SECTOR_SIZE]
+    }
+
+    /// Sets the FAT entry of `cluster` to `value` in both FATs.
+    pub fn set_fat(&mut self, cluster: u32, value: u32) {
+        for fat in 0..2 {
+            let start = MOCK_PARTITION_START + MOCK_RESERVED_SECTORS + fat * MOCK_SECTORS_PER_FAT;
+            let offset = start * MOCK_SECTOR_SIZE + cluster as usize * 4;
+            self.data[offset..offset + 4].copy_from_slice(&u32_le(value));
+        }
+    }
+
+    /// Sets the free cluster count and next free cluster hint in FSInfo.
+    pub fn set_fsinfo(&mut self, free_count: u32, next_free: u32) {
+        let fsinfo = (MOCK_PARTITION_START + 1) * MOCK_SECTOR_SIZE;
+        self.data[fsinfo + 488..fsinfo + 492].copy_from_slice(&u32_le(free_count));
+        self.data[fsinfo + 492..fsinfo + 496].copy_from_slice(&u32_le(next_free));
+    }
+
+    /// Adds a file with the 8.3 name `name` and contents `contents` to the
+    /// root directory and returns the slot of its directory entry.
+    pub fn add_file(&mut self, name: &str, contents: &[u8]) -> usize {
+        self.add_file_in(MOCK_ROOT, None, name, contents)
+    }
+
+    /// Like `add_file`, but precedes the 8.3 entry with LFN entries holding
+    /// `long_name`.
+    pub fn add_lfn_file(&mut self, long_name: &str, name: &str, contents: &[u8]) -> usize {
+        self.add_file_in(MOCK_ROOT, Some(long_name), name, contents)
+    }
+
+    /// Adds a file to the directory at cluster `dir` and returns the slot of
+    /// its 8.3 entry.
+    pub fn add_file_in(
+        &mut self,
+        dir: u32,
+        long_name: Option<&str>,
+        name: &str,
+        contents: &[u8],
+    ) -> usize {
+        let clusters = (contents.len() + MOCK_SECTOR_SIZE - 1) / MOCK_SECTOR_SIZE;
+        let first = self.allocate(clusters as u32);
+        let offset = MockImage::cluster_offset(first);
+        self.data[offset..offset + contents.len()].copy_from_slice(contents);
+        self.add_entry(dir, long_name, &short_name(name), 0x20, first, contents.len() as u32)
+    }
+
+    /// Adds an empty subdirectory, holding only `.` and `..`, to the directory
+    /// at cluster `parent` and returns the subdirectory's cluster.
+    pub fn add_dir(&mut self, parent: u32, long_name: Option<&str>, name: &str) -> u32 {
+        let cluster = self.allocate(1);
+        self.dir_slots.insert(cluster, 0);
+        self.add_entry(cluster, None, b".          ", 0x10, cluster, 0);
+        // `..` refers to the root directory as cluster 0.
+        let up = if parent == MOCK_ROOT { 0 } else { parent };
+        self.add_entry(cluster, None, b"..         ", 0x10, up, 0);
+        self.add_entry(parent, long_name, &short_name(name), 0x10, cluster, 0);
+        cluster
+    }
+
+    /// Chains `count` fresh clusters together and returns the first, or 0 if
+    /// `count` is 0.
+    fn allocate(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let first = self.next_cluster;
+        for i in 0..count {
+            let next = if i + 1 == count { 0x0FFFFFFF } else { first + i + 1 };
+            self.set_fat(first + i, next);
+        }
+        self.next_cluster += count;
+        ensure_len(&mut self.data, MockImage::cluster_offset(self.next_cluster));
+        let used = self.next_cluster - MOCK_ROOT;
+        let next = self.next_cluster;
+        self.set_fsinfo(MOCK_CLUSTERS as u32 - used, next);
+        first
+    }
+
+    fn add_entry(
+        &mut self,
+        dir: u32,
+        long_name: Option<&str>,
+        short: &[u8; 11],
+        attributes: u8,
+        first: u32,
+        size: u32,
+    ) -> usize {
+        if let Some(long_name) = long_name {
+            for lfn in lfn_entries(long_name, short) {
+                let entry = MockImage::slot_offset(dir, self.next_slot(dir));
+                self.data[entry..entry + 32].copy_from_slice(&lfn);
+            }
+        }
+
+        let slot = self.next_slot(dir);
+        let entry = MockImage::slot_offset(dir, slot);
+        self.data[entry..entry + 11].copy_from_slice(short);
+        self.data[entry + 11] = attributes;
+        self.data[entry + 20..entry + 22].copy_from_slice(&u16_le((first >> 16) as u16));
+        self.data[entry + 26..entry + 28].copy_from_slice(&u16_le(first as u16));
+        self.data[entry + 28..entry + 32].copy_from_slice(&u32_le(size));
+        slot
+    }
+
+    fn next_slot(&mut self, dir: u32) -> usize {
+        let slots = self.dir_slots.get_mut(&dir).expect("directory exists");
+        assert!(*slots < MOCK_SECTOR_SIZE / 32, "mock directory is full");
+        *slots += 1;
+        *slots - 1
+    }
+
+    /// Overwrites the size recorded in the root directory entry at `slot`.
+    pub fn set_size(&mut self, slot: usize, size: u32) {
+        let entry = MockImage::root_slot_offset(slot);
+        self.data[entry + 28..entry + 32].copy_from_slice(&u32_le(size));
+    }
+
+    pub fn mount(self) -> Shared<VFat> {
+        VFat::from(MockDevice::new(self.data)).expect("mock image mounts")
+    }
+
+    pub fn mount_with(self, config: VfatConfig) -> Shared<VFat> {
+        VFat::from_with_config(MockDevice::new(self.data), config).expect("mock image mounts")
+    }
+}
+
+/// Grows `data` with zeros, if need be, to hold at least `len` bytes.
+pub fn ensure_len(data: &mut Vec<u8>, len: usize) {
+    if data.len() < len {
+        data.resize(len, 0);
+    }
+}
+
+/// A device holding an image built by `MockImage`, possibly cut short after
+/// its last allocated cluster: sectors past the end of the data read as
+/// zeros, up to `MOCK_IMAGE_SIZE`, and writing one grows the data.
+pub struct MockDevice(pub Vec<u8>);
+
+impl MockDevice {
+    pub fn new(data: Vec<u8>) -> MockDevice {
+        MockDevice(data)
+    }
+}
+
+impl BlockDevice for MockDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let to_read = cmp::min(sector_size, buf.len());
+        let start = n as usize * sector_size;
+        if start + to_read > cmp::max(MOCK_IMAGE_SIZE, self.0.len()) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Read past the end of the image."));
+        }
+        let stored = match self.0.get(start..) {
+            Some(rest) => cmp::min(rest.len(), to_read),
+            None => 0,
+        };
+        if stored > 0 {
+            buf[..stored].copy_from_slice(&self.0[start..start + stored]);
+        }
+        for byte in &mut buf[stored..to_read] {
+            *byte = 0;
+        }
+        Ok(to_read)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let to_write = cmp::min(sector_size, buf.len());
+        let start = n as usize * sector_size;
+        ensure_len(&mut self.0, start + to_write);
+        self.0[start..start + to_write].copy_from_slice(&buf[..to_write]);
+        Ok(to_write)
+    }
+}
+
+pub fn u16_le(value: u16) -> [u8; 2] {
+    [value as u8, (value >> 8) as u8]
+}
+
+pub fn u32_le(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+/// Encodes `name` (e.g. `"HELLO.TXT"`) as the 11 bytes of a short name.
+pub fn short_name(name: &str) -> [u8; 11] {
+    let mut raw = [b' '; 11];
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+    raw[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    raw[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+    raw
+}
+
+/// Builds the LFN entries for `long_name`, in on-disk order, for the short
+/// name `short`.
+pub fn lfn_entries(long_name: &str, short: &[u8; 11]) -> Vec<[u8; 32]> {
+    let checksum = sfn_checksum(short);
+    let mut units: Vec<u16> = long_name.encode_utf16().collect();
+    if units.len() % 13 != 0 {
+        units.push(0x0000);
+    }
+    while units.len() % 13 != 0 {
+        units.push(0xFFFF);
+    }
+
+    let count = units.len() / 13;
+    let mut entries = Vec::new();
+    for (i, chunk) in units.chunks(13).enumerate() {
+        let mut entry = [0u8; 32];
+        entry[0] = (i + 1) as u8 | if i + 1 == count { 0x40 } else { 0 };
+        entry[11] = 0x0F;
+        entry[13] = checksum;
+        let offsets = (0..5).map(|j| 1 + 2 * j)
+            .chain((0..6).map(|j| 14 + 2 * j))
+            .chain((0..2).map(|j| 28 + 2 * j));
+        for (offset, unit) in offsets.zip(chunk) {
+            entry[offset..offset + 2].copy_from_slice(&u16_le(*unit));
+        }
+        entries.push(entry);
+    }
+    entries.reverse();
+    entries
+}
+
+/// A device whose contents remain inspectable after it is handed to `VFat`.
+#[cfg(feature = "write")]
+#[derive(Clone)]
+pub struct SharedDevice(pub Arc<Mutex<MockDevice>>);
+
+#[cfg(feature = "write")]
+impl SharedDevice {
+    pub fn new(data: Vec<u8>) -> SharedDevice {
+        SharedDevice(Arc::new(Mutex::new(MockDevice::new(data))))
+    }
+
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        let data = self.0.lock().unwrap();
+        let bytes = data.0.get(offset..offset + 4).unwrap_or(&[0; 4]);
+        bytes.iter().rev().fold(0, |value, &b| value << 8 | b as u32)
+    }
+
+    pub fn fat(&self, fat: usize, cluster: u32) -> u32 {
+        let start = MOCK_PARTITION_START + MOCK_RESERVED_SECTORS + fat * MOCK_SECTORS_PER_FAT;
+        self.read_u32(start * MOCK_SECTOR_SIZE + cluster as usize * 4)
+    }
+}
+
+#[cfg(feature = "write")]
+impl BlockDevice for SharedDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write_sector(n, buf)
+    }
+}
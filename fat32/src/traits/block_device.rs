@@ -18,6 +18,28 @@ pub trait BlockDevice: Send {
     /// Returns an error if seeking or reading from `self` fails.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize>;
 
+    /// Fills `buf` with sector `n` and, if `buf` is longer than a sector, the
+    /// sectors following it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `UnexpectedEof` if a sector can't be read in full.
+    /// Returns an error if seeking or reading from `self` fails.
+    fn read_sector_exact(&mut self, n: u64, buf: &mut [u8]) -> io::Result<()> {
+        let sector_size = self.sector_size() as usize;
+        let mut sector = n;
+        for chunk in buf.chunks_mut(sector_size) {
+            if self.read_sector(sector, chunk)? < chunk.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Sector could not be read in full.",
+                ));
+            }
+            sector += 1;
+        }
+        Ok(())
+    }
+
     /// Append sector number `n` into `vec`.
     ///
     /// `self.sector_size()` bytes are appended to `vec`. The number of bytes
@@ -93,5 +115,5 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
 impl_for_read_write_seek!(<'a> ::std::io::Cursor<&'a mut [u8]>);
 impl_for_read_write_seek!(::std::io::Cursor<Vec<u8>>);
 impl_for_read_write_seek!(::std::io::Cursor<Box<[u8]>>);
-#[cfg(test)]
+#[cfg(any(test, feature = "std"))]
 impl_for_read_write_seek!(::std::fs::File);
@@ -108,6 +108,18 @@ impl Metadata for Dummy {
     fn hidden(&self) -> bool {
         panic!("Dummy")
     }
+    fn system(&self) -> bool {
+        panic!("Dummy")
+    }
+    fn archive(&self) -> bool {
+        panic!("Dummy")
+    }
+    fn volume_id(&self) -> bool {
+        panic!("Dummy")
+    }
+    fn is_dir(&self) -> bool {
+        panic!("Dummy")
+    }
     fn created(&self) -> Self::Timestamp {
         panic!("Dummy")
     }
@@ -0,0 +1,171 @@
+use std::io;
+
+use traits::BlockDevice;
+
+/// The operations a fault applies to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultOp {
+    Read,
+    Write,
+    Both,
+}
+
+impl FaultOp {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            FaultOp::Read => !write,
+            FaultOp::Write => write,
+            FaultOp::Both => true,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Trigger {
+    /// Every operation on the sector.
+    Sector(u64),
+    /// Every operation once this many have succeeded.
+    After(u64),
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Effect {
+    Error(io::ErrorKind),
+    /// Only this many bytes are transferred.
+    Short(usize),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Fault {
+    op: FaultOp,
+    trigger: Trigger,
+    effect: Effect,
+}
+
+/// A `BlockDevice` wrapper that fails chosen operations of the device it
+/// wraps, for exercising error handling.
+///
+/// Faults are added with `fail_sector`, `fail_after` and `short_read`, and
+/// checked in the order they were added; the first one that applies decides
+/// the outcome. Operations no fault applies to are passed through unchanged.
+#[derive(Debug)]
+pub struct FaultyDevice<T> {
+    inner: T,
+    faults: Vec<Fault>,
+    reads: u64,
+    writes: u64,
+}
+
+impl<T: BlockDevice> FaultyDevice<T> {
+    /// Wraps `inner` with no faults scheduled.
+    pub fn new(inner: T) -> FaultyDevice<T> {
+        FaultyDevice {
+            inner,
+            faults: Vec::new(),
+            reads: 0,
+            writes: 0,
+        }
+    }
+
+    /// Makes every `op` on sector `sector` fail with an error of `kind`.
+    pub fn fail_sector(&mut self, op: FaultOp, sector: u64, kind: io::ErrorKind) {
+        self.add(op, Trigger::Sector(sector), Effect::Error(kind));
+    }
+
+    /// Lets `count` more `op`s succeed, then makes every later one fail with
+    /// an error of `kind`. For `FaultOp::Both`, reads and writes are counted
+    /// together.
+    pub fn fail_after(&mut self, op: FaultOp, count: u64, kind: io::ErrorKind) {
+        let done = match op {
+            FaultOp::Read => self.reads,
+            FaultOp::Write => self.writes,
+            FaultOp::Both => self.reads + self.writes,
+        };
+        self.add(op, Trigger::After(done + count), Effect::Error(kind));
+    }
+
+    /// Makes reads of sector `sector` return at most `len` bytes.
+    pub fn short_read(&mut self, sector: u64, len: usize) {
+        self.add(FaultOp::Read, Trigger::Sector(sector), Effect::Short(len));
+    }
+
+    /// Removes every scheduled fault.
+    pub fn clear_faults(&mut self) {
+        self.faults.clear();
+    }
+
+    /// The number of reads and writes attempted so far, failed or not.
+    pub fn operations(&self) -> (u64, u64) {
+        (self.reads, self.writes)
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Unwraps this `FaultyDevice`, returning the wrapped device.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn add(&mut self, op: FaultOp, trigger: Trigger, effect: Effect) {
+        self.faults.push(Fault { op, trigger, effect });
+    }
+
+    /// Counts an operation on sector `n` and returns the effect of the first
+    /// fault that applies to it, if any.
+    fn check(&mut self, n: u64, write: bool) -> Option<Effect> {
+        let (reads, writes) = (self.reads, self.writes);
+        if write {
+            self.writes += 1;
+        } else {
+            self.reads += 1;
+        }
+        self.faults
+            .iter()
+            .find(|fault| {
+                fault.op.matches(write) &&
+                    match fault.trigger {
+                        Trigger::Sector(sector) => sector == n,
+                        Trigger::After(count) => {
+                            let done = match fault.op {
+                                FaultOp::Read => reads,
+                                FaultOp::Write => writes,
+                                FaultOp::Both => reads + writes,
+                            };
+                            done >= count
+                        }
+                    }
+            })
+            .map(|fault| fault.effect)
+    }
+}
+
+impl<T: BlockDevice> BlockDevice for FaultyDevice<T> {
+    fn sector_size(&self) -> u64 {
+        self.inner.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        match self.check(n, false) {
+            None => self.inner.read_sector(n, buf),
+            Some(Effect::Error(kind)) => Err(io::Error::new(kind, "Injected read fault.")),
+            Some(Effect::Short(len)) => {
+                let len = ::std::cmp::min(len, buf.len());
+                self.inner.read_sector(n, &mut buf[..len])
+            }
+        }
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        match self.check(n, true) {
+            None => self.inner.write_sector(n, buf),
+            Some(Effect::Error(kind)) => Err(io::Error::new(kind, "Injected write fault.")),
+            Some(Effect::Short(len)) => {
+                let len = ::std::cmp::min(len, buf.len());
+                self.inner.write_sector(n, &buf[..len])
+            }
+        }
+    }
+}
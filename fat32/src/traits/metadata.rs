@@ -34,6 +34,18 @@ pub trait Metadata: Sized {
     /// Whether the entry should be "hidden" from directory traversals.
     fn hidden(&self) -> bool;
 
+    /// Whether the entry belongs to the operating system.
+    fn system(&self) -> bool;
+
+    /// Whether the entry has changed since it was last archived.
+    fn archive(&self) -> bool;
+
+    /// Whether the entry holds the volume label rather than a file.
+    fn volume_id(&self) -> bool;
+
+    /// Whether the entry is a directory.
+    fn is_dir(&self) -> bool;
+
     /// The timestamp when the entry was created.
     fn created(&self) -> Self::Timestamp;
 
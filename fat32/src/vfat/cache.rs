@@ -20,6 +20,8 @@ pub struct CachedDevice {
     device: Box<BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
     partition: Partition,
+    /// The most sectors to keep cached, if limited.
+    capacity: Option<usize>,
 }
 
 impl CachedDevice {
@@ -52,6 +54,38 @@ impl CachedDevice {
             device: Box::new(device),
             cache: HashMap::new(),
             partition: partition,
+            capacity: None,
+        }
+    }
+
+    /// Limits the cache to `capacity` sectors, or lifts the limit if `None`.
+    /// Once the cache is full, loading a sector evicts a clean one. Dirty
+    /// sectors are never evicted, so the cache may exceed its capacity when
+    /// too many of them are held.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    /// Returns `true` if `sectors` more sectors can be cached without evicting
+    /// any.
+    pub fn has_room_for(&self, sectors: usize) -> bool {
+        match self.capacity {
+            Some(capacity) => self.cache.len() + sectors <= capacity,
+            None => true,
+        }
+    }
+
+    /// Evicts a clean sector other than `keep` if the cache is full.
+    fn make_room(&mut self, keep: u64) {
+        if self.has_room_for(1) || self.cache.contains_key(&keep) {
+            return;
+        }
+        let victim = self.cache
+            .iter()
+            .find(|&(&sector, entry)| sector != keep && !entry.dirty)
+            .map(|(&sector, _)| sector);
+        if let Some(victim) = victim {
+            self.cache.remove(&victim);
         }
     }
 
@@ -83,11 +117,12 @@ impl CachedDevice {
                 &mut cached_sector[s..e],
             )?;
         }
+        self.make_room(sector);
         Ok(self.cache.insert(
             sector,
             CacheEntry {
                 data: cached_sector,
-                dirty: true,
+                dirty: false,
             },
         ))
     }
@@ -112,7 +147,9 @@ impl CachedDevice {
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
         self.ensure_cached(sector)?; // 🌶🐔 lifetime check
-        Ok(self.cache.get_mut(&sector).unwrap().data.as_mut())
+        let entry = self.cache.get_mut(&sector).unwrap();
+        entry.dirty = true;
+        Ok(entry.data.as_mut())
     }
 
     /// Returns a reference to the cached sector `sector`. If the sector is not
@@ -125,6 +162,32 @@ impl CachedDevice {
         self.ensure_cached(sector)?;
         Ok(self.cache.get(&sector).unwrap().data.as_ref())
     }
+
+    /// Writes every dirty cached sector back to the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing a sector to the disk fails. Sectors that
+    /// were not written remain dirty.
+    #[cfg(feature = "write")]
+    pub fn sync(&mut self) -> io::Result<()> {
+        let dirty: Vec<u64> = self.cache
+            .iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+        for sector in dirty {
+            let (physical_sector, number) = self.virtual_to_physical(sector);
+            let entry = self.cache.get_mut(&sector).unwrap();
+            for i in 0..number {
+                let s = (i * self.device.sector_size()) as usize;
+                let e = ((i + 1) * self.device.sector_size()) as usize;
+                self.device.write_sector(physical_sector + i, &entry.data[s..e])?;
+            }
+            entry.dirty = false;
+        }
+        Ok(())
+    }
 }
 
 // FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
 pub struct Cluster(u32);
 
@@ -8,9 +10,26 @@ impl From<u32> for Cluster {
 }
 
 impl Cluster {
+    /// Creates the cluster numbered `number`. As with `From<u32>`, the upper
+    /// four bits, which are reserved in a FAT32 entry, are cleared.
+    pub fn new(number: u32) -> Cluster {
+        number.into()
+    }
+
     pub fn inner(&self) -> u32 {
         self.0
     }
+
+    /// Returns the cluster number. The same as `inner`.
+    pub fn number(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 // TODO: Implement any useful helper methods on `Cluster`.
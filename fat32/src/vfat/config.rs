@@ -0,0 +1,81 @@
+use vfat::OemCodec;
+
+/// Options controlling how strictly a `VFat` checks the on-disk structures it
+/// reads. `VfatConfig::default()` is what `VFat::from` uses.
+#[derive(Debug, Clone)]
+pub struct VfatConfig {
+    /// When set, opening a file whose recorded size is larger than the
+    /// clusters allocated to it fails with `InvalidData`. Off by default.
+    pub strict_size: bool,
+    /// When set, listing a directory whose entry records no first cluster
+    /// (other than a `..` referring to the root) fails with `InvalidData`;
+    /// otherwise such a directory lists as empty. Off by default.
+    pub strict_dirs: bool,
+    /// The most 32-byte slots (including LFN and deleted ones) a directory
+    /// may hold before its terminator; listing a larger directory fails with
+    /// `InvalidData`. Defaults to 65536, the limit set by the specification.
+    pub max_dir_entries: usize,
+    /// When set, `VFat::alloc_contiguous` falls back to a fragmented chain if
+    /// no run of free clusters is long enough, instead of failing. Off by
+    /// default.
+    pub fragmented_fallback: bool,
+    /// When set, mounting fails with `InvalidData` unless
+    /// `VFat::validate_root` accepts the root directory's cluster chain. Off
+    /// by default.
+    pub validate_root: bool,
+    /// The code page 8.3 short names are decoded with. Long file names are
+    /// UTF-16 and unaffected. Defaults to code page 437.
+    pub oem_codec: OemCodec,
+    /// The most sectors the sector cache holds, or `None` for no limit. Also
+    /// bounds how much `VFat::warm_cache_for_tree` reads. Unlimited by
+    /// default.
+    pub cache_capacity: Option<usize>,
+    /// When set, files and directories opened from a listing keep the raw
+    /// bytes of their names; see `File::raw_name_bytes`. Off by default.
+    pub keep_raw_names: bool,
+    /// When set, the MBR is read with `MasterBootRecord::from_lenient`, so an
+    /// invalid boot indicator in an unused partition entry does not prevent
+    /// mounting. Off by default.
+    pub lenient_boot_indicators: bool,
+    /// The deepest a traversal of the directory tree (`walk`, `extract`,
+    /// `VFat::warm_cache_for_tree`, `VFat::find_entry_for_cluster`) descends
+    /// below its starting directory before failing with `InvalidData`. Guards
+    /// against directories that, through corruption, contain an ancestor.
+    /// Defaults to 256.
+    pub max_depth: usize,
+    /// With write support, the most bytes of consecutive small writes a
+    /// `File` holds in memory before writing them to the volume at once, or
+    /// `None` to write every call through. Clusters and the file's size are
+    /// still updated by every write; only the bytes are held. They are
+    /// written when the next write doesn't continue them or would overflow
+    /// the buffer, on `flush` and `sync`, before the file is read or cloned,
+    /// and when it is dropped. Off by default.
+    pub write_buffer_size: Option<usize>,
+    /// The largest file, in bytes, that may be opened or read, or `None` for
+    /// no limit beyond the size of the volume. Opening a larger file fails
+    /// with `InvalidData`, as does `File::read_all_into` on one, and
+    /// `VFat::read_chain` on a chain longer than the limit rounded up to
+    /// whole clusters. Bounds the memory a hostile image can make those
+    /// allocate. Directories are read within `max_dir_entries` instead.
+    /// Unlimited by default.
+    pub max_file_size: Option<u64>,
+}
+
+impl Default for VfatConfig {
+    fn default() -> VfatConfig {
+        VfatConfig {
+            strict_size: false,
+            strict_dirs: false,
+            max_dir_entries: 65536,
+            fragmented_fallback: false,
+            validate_root: false,
+            oem_codec: OemCodec::cp437(),
+            cache_capacity: None,
+            keep_raw_names: false,
+            lenient_boot_indicators: false,
+            max_depth: 256,
+            write_buffer_size: None,
+            max_file_size: None,
+        }
+    }
+}
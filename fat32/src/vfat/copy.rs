@@ -0,0 +1,35 @@
+use std::io;
+use std::path::Path;
+
+use traits::{self, Entry, FileSystem};
+use vfat::{Shared, VFat};
+
+impl Shared<VFat> {
+    /// Copies the file at `from` to a new file at `to` on the same volume and
+    /// returns the number of bytes copied. The copy gets clusters of its own;
+    /// no chain is shared with the original.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `from` is a directory. Otherwise
+    /// fails as `open` does for `from` and as `create_file` does for `to`.
+    pub fn copy<P, Q>(&self, from: P, to: Q) -> io::Result<u64>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let mut source = match self.open(from)?.into_file() {
+            Some(file) => file,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Only files can be copied.",
+                ))
+            }
+        };
+        let mut destination = self.create_file(to)?;
+        let copied = io::copy(&mut source, &mut destination)?;
+        traits::File::sync(&mut destination)?;
+        Ok(copied)
+    }
+}
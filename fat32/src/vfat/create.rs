@@ -0,0 +1,360 @@
+use std::cmp::min;
+use std::io;
+use std::path::Path;
+
+use traits::{Dir as DirTrait, Entry as EntryTrait, FileSystem};
+use vfat::{sfn_checksum, Cluster, Dir, DirEntryParser, Entry, File, Shared, VFat};
+use vfat::dir::{make_entry, EntrySlot};
+use vfat::vfat::validate_file_name_of;
+
+/// The attributes byte of a newly created file.
+const ARCHIVE: u8 = 0x20;
+
+/// The attributes byte of a newly created directory.
+const DIRECTORY: u8 = 0x10;
+
+/// The date stamped on new entries: 1980-01-01, the earliest FAT can store,
+/// as there is no clock to read the current date from.
+const EPOCH_DATE: u16 = (1 << 5) | 1;
+
+/// Creates an empty file at `path`, whose last component has been checked
+/// with `validate_filename`, and returns it.
+pub(crate) fn create_file(vfat: &Shared<VFat>, path: &Path) -> io::Result<File> {
+    let parent = ensure_vacant(vfat, path)?;
+    match add_entry(vfat, &parent, file_name(path), &fields(ARCHIVE, Cluster::from(0)), None)? {
+        Entry::File(file) => Ok(file),
+        Entry::Dir(_) => unreachable!("entry was created without the directory attribute"),
+    }
+}
+
+/// Creates an empty directory at `path`, whose last component has been
+/// checked with `validate_filename`, and returns it. If `parents` is set,
+/// missing directories leading up to it are created first.
+pub(crate) fn create_dir(vfat: &Shared<VFat>, path: &Path, parents: bool) -> io::Result<Dir> {
+    if let (true, Some(parent)) = (parents, path.parent()) {
+        match vfat.open(parent) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                validate_file_name_of(parent)?;
+                create_dir(vfat, parent, true)?;
+            }
+            _ => {}
+        }
+    }
+    let parent = ensure_vacant(vfat, path)?;
+    let cluster = {
+        let mut vfat = vfat.try_borrow_mut()?;
+        let parent_cluster = dot_dot_cluster(&vfat, &parent);
+        let cluster = vfat.alloc_contiguous(1)?;
+        vfat.zero_cluster(cluster)?;
+        let mut dots = [0u8; 64];
+        dots[..32].copy_from_slice(&fields(DIRECTORY, cluster));
+        dots[..11].copy_from_slice(b".          ");
+        dots[32..].copy_from_slice(&fields(DIRECTORY, parent_cluster));
+        dots[32..43].copy_from_slice(b"..         ");
+        vfat.write_cluster(cluster, 0, &dots)?;
+        cluster
+    };
+    match add_entry(vfat, &parent, file_name(path), &fields(DIRECTORY, cluster), None) {
+        Ok(Entry::Dir(dir)) => Ok(dir),
+        Ok(Entry::File(_)) => unreachable!("entry was created with the directory attribute"),
+        Err(e) => {
+            // Nothing refers to the new cluster yet: give it back.
+            vfat.try_borrow_mut()?.free_chain(cluster)?;
+            Err(e)
+        }
+    }
+}
+
+/// Moves the entry at `from` to `to`, whose last component has been checked
+/// with `validate_filename`. The entry keeps its attributes, timestamps and
+/// clusters. Handles to the entry opened before the move must not be
+/// written through afterwards.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `from` is the root directory or a
+/// directory `to` lies within.
+pub(crate) fn rename(vfat: &Shared<VFat>, from: &Path, to: &Path) -> io::Result<()> {
+    let entry = vfat.open(from)?;
+    let moved_dir = match entry {
+        Entry::Dir(ref dir) => Some(dir.first_cluster()),
+        Entry::File(_) => None,
+    };
+    let slot = entry_slot_of(&entry).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "The root directory cannot be renamed.")
+    })?;
+
+    // Changing only the case of a name renames the entry onto itself.
+    let same_entry = from.parent() == to.parent() &&
+        match from.file_name().and_then(|name| name.to_str()) {
+            Some(from_name) => from_name.eq_ignore_ascii_case(file_name(to)),
+            None => false,
+        };
+    let parent = if same_entry {
+        open_parent(vfat, to)?
+    } else {
+        ensure_vacant(vfat, to)?
+    };
+    if let Some(moved) = moved_dir {
+        let mut ancestor = to.parent();
+        while let Some(path) = ancestor {
+            if vfat.open(path)?.into_dir().map(|dir| dir.first_cluster()) == Some(moved) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "A directory cannot be moved into itself.",
+                ));
+            }
+            ancestor = path.parent();
+        }
+    }
+
+    let fields = vfat.try_borrow_mut()?.entry_slot_bytes(slot)?;
+    add_entry(vfat, &parent, file_name(to), &fields, Some(slot))?;
+    let mut vfat = vfat.try_borrow_mut()?;
+    vfat.delete_entry(slot)?;
+    match moved_dir {
+        Some(moved) if parent.first_cluster() != slot.dir => {
+            let parent_cluster = dot_dot_cluster(&vfat, &parent);
+            vfat.set_entry_cluster_and_size(EntrySlot { dir: moved, index: 1 }, parent_cluster, 0)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the last component of `path`, which has been checked with
+/// `validate_filename`.
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(|name| name.to_str()).expect("name was validated")
+}
+
+/// Returns an 8.3 slot with no name, the attributes byte `attributes`, the
+/// first cluster `first_cluster`, and `EPOCH_DATE` as its creation, access
+/// and modification dates.
+fn fields(attributes: u8, first_cluster: Cluster) -> [u8; 32] {
+    let mut slot = [0u8; 32];
+    slot[11] = attributes;
+    let date = [EPOCH_DATE as u8, (EPOCH_DATE >> 8) as u8];
+    for &at in [16, 18, 24].iter() {
+        slot[at..at + 2].copy_from_slice(&date);
+    }
+    let cluster = first_cluster.inner();
+    slot[20..22].copy_from_slice(&[(cluster >> 16) as u8, (cluster >> 24) as u8]);
+    slot[26..28].copy_from_slice(&[cluster as u8, (cluster >> 8) as u8]);
+    slot
+}
+
+/// Returns the cluster the `..` entry of a directory in `parent` records:
+/// that of `parent`, or 0 for the root.
+fn dot_dot_cluster(vfat: &VFat, parent: &Dir) -> Cluster {
+    if parent.first_cluster() == vfat.root_dir_cluster {
+        Cluster::from(0)
+    } else {
+        parent.first_cluster()
+    }
+}
+
+fn entry_slot_of(entry: &Entry) -> Option<EntrySlot> {
+    match *entry {
+        Entry::File(ref file) => file.entry_slot(),
+        Entry::Dir(ref dir) => dir.entry_slot(),
+    }
+}
+
+/// Opens the directory holding `path`.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if there is no such directory.
+fn open_parent(vfat: &Shared<VFat>, path: &Path) -> io::Result<Dir> {
+    let not_a_dir = || {
+        io::Error::new(io::ErrorKind::InvalidInput, "A Component of Path is not a directory.")
+    };
+    let parent = path.parent().unwrap_or(path);
+    match vfat.open(parent) {
+        Ok(entry) => entry.into_dir().ok_or_else(not_a_dir),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(not_a_dir()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Checks that no entry of the directory holding `path` already goes by the
+/// last component of `path`, and returns that directory. Names are compared
+/// case-insensitively, as by `Dir::find`, and against both long and short
+/// names, as other FAT implementations would resolve them.
+///
+/// # Errors
+///
+/// Returns an error of `AlreadyExists` if the name is taken, or of
+/// `InvalidInput` if the parent of `path` is not a directory.
+fn ensure_vacant(vfat: &Shared<VFat>, path: &Path) -> io::Result<Dir> {
+    let name = file_name(path);
+    let dir = open_parent(vfat, path)?;
+    let taken = dir.entries()?.any(|entry| {
+        let short_name = match entry {
+            Entry::File(ref file) => file.short_name(),
+            Entry::Dir(ref dir) => dir.short_name(),
+        };
+        entry.name().eq_ignore_ascii_case(name) || short_name.eq_ignore_ascii_case(name)
+    });
+    if taken {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "An entry with this name already exists.",
+        ));
+    }
+    Ok(dir)
+}
+
+/// Adds an entry named `name` to `parent` and returns it. Bytes 11 to 32 of
+/// its 8.3 slot, which hold the attributes, timestamps, first cluster and
+/// size, are taken from `fields`.
+///
+/// The name is stored as an 8.3 name if it is one. Otherwise a long file name
+/// is stored along with an 8.3 name derived from it that no entry of `parent`
+/// uses, other than the entry at `replacing`, which is about to be removed.
+fn add_entry(
+    vfat: &Shared<VFat>,
+    parent: &Dir,
+    name: &str,
+    fields: &[u8; 32],
+    replacing: Option<EntrySlot>,
+) -> io::Result<Entry> {
+    let taken = parent.entries()?.filter(|entry| entry_slot_of(entry) != replacing).map(|entry| match entry {
+        Entry::File(ref file) => file.short_name(),
+        Entry::Dir(ref dir) => dir.short_name(),
+    }).collect::<Vec<_>>();
+    let mut slots = name_slots(name, &taken)?;
+    slots.last_mut().expect("there is always an 8.3 slot")[11..].copy_from_slice(&fields[11..]);
+    let index = vfat.try_borrow_mut()?.add_dir_entries(parent.first_cluster(), &slots)?;
+
+    let bytes = slots.iter().flat_map(|slot| slot.iter().cloned()).collect::<Vec<u8>>();
+    let mut entry = DirEntryParser::from_bytes(&bytes).next().expect("slots just built parse");
+    entry.slot += index;
+    Ok(make_entry(entry, parent.first_cluster(), vfat))
+}
+
+/// Returns the directory slots storing `name`: LFN slots if needed, then an
+/// 8.3 slot holding only the 8.3 name. `taken` lists the 8.3 names already in
+/// use, in `NAME.EXT` form.
+///
+/// # Errors
+///
+/// Returns an error of `Other` if every 8.3 name `name` could be given is
+/// taken.
+fn name_slots(name: &str, taken: &[String]) -> io::Result<Vec<[u8; 32]>> {
+    let upper = name.to_ascii_uppercase();
+    let (short_name, lossless) = match exact_short_name(&upper) {
+        Some(short_name) => (short_name, upper == name),
+        None => (numbered_short_name(name, taken)?, false),
+    };
+    let mut slots = if lossless {
+        Vec::new()
+    } else {
+        lfn_slots(name, sfn_checksum(&short_name))
+    };
+    let mut sfn = [0u8; 32];
+    sfn[..11].copy_from_slice(&short_name);
+    slots.push(sfn);
+    Ok(slots)
+}
+
+/// Whether `c` may appear in an 8.3 name created here.
+fn is_short_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+/// Returns `name` as the 11 bytes of an 8.3 name if it is one already: at
+/// most 8 characters, optionally followed by a dot and at most 3 more, all
+/// of them allowed in 8.3 names (so uppercase).
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension) = match name.find('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    let valid = |part: &str, max: usize| part.len() <= max && part.bytes().all(is_short_name_char);
+    if base.is_empty() || !valid(base, 8) || !valid(extension, 3) ||
+        (name.ends_with('.') && extension.is_empty())
+    {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+    Some(short_name)
+}
+
+/// Derives an 8.3 name of the form `BASE~N.EXT` from the long name `name`,
+/// picking the lowest `N` whose name is not in `taken`.
+fn numbered_short_name(name: &str, taken: &[String]) -> io::Result<[u8; 11]> {
+    // Spaces and dots are dropped and other characters not allowed in 8.3
+    // names become `_`.
+    let convert = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c.to_ascii_uppercase() {
+                c if c.is_ascii() && is_short_name_char(c as u8) => c as u8,
+                _ => b'_',
+            })
+            .collect()
+    };
+    let trimmed = &name[name.find(|c| c != '.').unwrap_or(name.len())..];
+    let (base, extension) = match trimmed.rfind('.') {
+        Some(dot) => (convert(&trimmed[..dot]), convert(&trimmed[dot + 1..])),
+        None => (convert(trimmed), Vec::new()),
+    };
+    let extension = &extension[..min(extension.len(), 3)];
+
+    for n in 1..1000000 {
+        let tail = format!("~{}", n);
+        let kept = min(base.len(), 8 - tail.len());
+        let mut short_name = [b' '; 11];
+        short_name[..kept].copy_from_slice(&base[..kept]);
+        short_name[kept..kept + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + extension.len()].copy_from_slice(extension);
+
+        let mut display = String::from_utf8_lossy(&short_name[..kept + tail.len()]).into_owned();
+        if !extension.is_empty() {
+            display.push('.');
+            display.push_str(&String::from_utf8_lossy(extension));
+        }
+        if !taken.iter().any(|taken| taken.eq_ignore_ascii_case(&display)) {
+            return Ok(short_name);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "No unused 8.3 name is left for this name.",
+    ))
+}
+
+/// Returns the LFN slots storing `name` for the 8.3 name with checksum
+/// `checksum`, in the order they precede it on disk.
+fn lfn_slots(name: &str, checksum: u8) -> Vec<[u8; 32]> {
+    let mut units = name.encode_utf16().collect::<Vec<u16>>();
+    // A name that doesn't fill its last slot is terminated and padded.
+    if units.len() % 13 != 0 {
+        units.push(0x0000);
+        while units.len() % 13 != 0 {
+            units.push(0xFFFF);
+        }
+    }
+    let count = units.len() / 13;
+    (0..count).rev().map(|i| {
+        let mut slot = [0u8; 32];
+        slot[0] = (i + 1) as u8 | if i + 1 == count { 0x40 } else { 0 };
+        slot[11] = 0x0F;
+        slot[13] = checksum;
+        for (j, &c) in units[i * 13..(i + 1) * 13].iter().enumerate() {
+            let at = if j < 5 {
+                1 + j * 2
+            } else if j < 11 {
+                14 + (j - 5) * 2
+            } else {
+                28 + (j - 11) * 2
+            };
+            slot[at] = c as u8;
+            slot[at + 1] = (c >> 8) as u8;
+        }
+        slot
+    }).collect()
+}
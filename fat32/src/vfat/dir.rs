@@ -1,34 +1,81 @@
+use std::cmp;
+use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::io;
 use std::iter;
+use std::mem;
+use std::path::{Component, Path};
 use std::vec;
 
 use traits;
-use util::VecExt;
 use vfat::{Attributes, Date, Metadata, Time, ROOTMETADATA};
-use vfat::{Cluster, Entry, File, Shared, VFat};
+use vfat::{Cluster, Entry, File, OemCodec, Shared, VFat};
 
-#[derive(Debug)]
+/// A directory in a `VFat` file system. Cloning a `Dir` is cheap.
+#[derive(Debug, Clone)]
 pub struct Dir {
     pub name: String,
+    short_name: String,
     pub metadata: Metadata,
     first_cluster: Cluster,
     vfat: Shared<VFat>,
+    raw_name: Vec<u8>,
+    /// Where the directory's own 8.3 slot is, if it was found in a listing.
+    entry_slot: Option<EntrySlot>,
+}
+
+/// Where the 8.3 slot describing a file or directory is: the first cluster
+/// of the directory holding it, and the index of the slot in that directory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct EntrySlot {
+    pub dir: Cluster,
+    pub index: usize,
 }
 
 impl Dir {
-    fn new(name: String, metadata: Metadata, first_cluster: Cluster, vfat: Shared<VFat>) -> Dir {
+    fn new(
+        name: String,
+        short_name: String,
+        metadata: Metadata,
+        first_cluster: Cluster,
+        vfat: Shared<VFat>,
+    ) -> Dir {
         Dir {
             name,
+            short_name,
             metadata,
             first_cluster,
             vfat,
+            raw_name: Vec::new(),
+            entry_slot: None,
         }
     }
 
     pub(crate) fn root_from_vfat(vfat: Shared<VFat>) -> Dir {
         let root_dir_cluster = vfat.borrow().root_dir_cluster;
-        Self::new(String::from(""), ROOTMETADATA, root_dir_cluster, vfat)
+        Self::new(String::from(""), String::from(""), ROOTMETADATA, root_dir_cluster, vfat)
+    }
+
+    /// The first cluster of the directory, as accepted by `VFat::path_of`.
+    pub fn first_cluster(&self) -> Cluster {
+        self.first_cluster
+    }
+
+    #[cfg(any(feature = "write", feature = "fuse"))]
+    pub(crate) fn entry_slot(&self) -> Option<EntrySlot> {
+        self.entry_slot
+    }
+
+    /// The 8.3 short name of the directory. It is the same as `name` unless
+    /// the directory has a long file name.
+    pub fn short_name(&self) -> String {
+        self.short_name.clone()
+    }
+
+    /// The name bytes exactly as stored, laid out as for
+    /// `File::raw_name_bytes`. Always empty for the root directory.
+    pub fn raw_name_bytes(&self) -> &[u8] {
+        &self.raw_name
     }
 }
 
@@ -115,12 +162,101 @@ pub struct VFatUnknownDirEntry {
     __r1: [u8; 20],
 }
 
+#[derive(Copy, Clone)]
 pub union VFatDirEntry {
     unknown: VFatUnknownDirEntry,
     regular: VFatRegularDirEntry,
     long_filename: VFatLfnDirEntry,
 }
 
+impl VFatDirEntry {
+    /// Decodes the 32-byte slot `bytes` field by field: as an LFN entry if its
+    /// attributes say so, otherwise as a regular one. Multi-byte fields are
+    /// read as little-endian, and `bytes` needs no particular alignment.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` is shorter than 32 bytes.
+    fn decode(bytes: &[u8]) -> VFatDirEntry {
+        let u16_at = |offset: usize| bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8;
+        let attributes = Attributes::from(bytes[11]);
+        if attributes.lfn() {
+            // The 13 name characters are split into runs at offsets 1, 14 and
+            // 28.
+            let units_at = |offset: usize, units: &mut [u16]| {
+                for (i, unit) in units.iter_mut().enumerate() {
+                    *unit = u16_at(offset + 2 * i);
+                }
+            };
+            let (mut part_1, mut part_2, mut part_3) = ([0; 5], [0; 6], [0; 2]);
+            units_at(1, &mut part_1);
+            units_at(14, &mut part_2);
+            units_at(28, &mut part_3);
+            let entry = VFatLfnDirEntry {
+                seq_num: bytes[0],
+                name_characters_1: part_1,
+                attributes,
+                type_: bytes[12],
+                checksum: bytes[13],
+                name_characters_2: part_2,
+                __r0: u16_at(26),
+                name_characters_3: part_3,
+            };
+            VFatDirEntry { long_filename: entry }
+        } else {
+            let mut name = [0; 8];
+            let mut extension = [0; 3];
+            name.copy_from_slice(&bytes[0..8]);
+            extension.copy_from_slice(&bytes[8..11]);
+            VFatDirEntry {
+                regular: VFatRegularDirEntry {
+                    name,
+                    extension,
+                    attributes,
+                    __r0: bytes[12],
+                    _creation_time: bytes[13],
+                    ctime: Time::from(u16_at(14)),
+                    cdate: Date::from(u16_at(16)),
+                    adate: Date::from(u16_at(18)),
+                    first_cluster_higher_bits: u16_at(20),
+                    mtime: Time::from(u16_at(22)),
+                    mdate: Date::from(u16_at(24)),
+                    first_cluster_lower_bits: u16_at(26),
+                    size: u16_at(28) as u32 | (u16_at(30) as u32) << 16,
+                },
+            }
+        }
+    }
+}
+
+/// The entries in a buffer of 32-byte directory slots, decoded one at a time
+/// with `VFatDirEntry::decode`. A trailing partial slot is ignored.
+pub(crate) struct RawSlots {
+    buf: Vec<u8>,
+    offset: usize,
+}
+
+impl RawSlots {
+    pub(crate) fn new(buf: Vec<u8>) -> RawSlots {
+        RawSlots { buf, offset: 0 }
+    }
+}
+
+impl iter::Iterator for RawSlots {
+    type Item = VFatDirEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slot = self.buf.get(self.offset..self.offset + 32)?;
+        self.offset += 32;
+        Some(VFatDirEntry::decode(slot))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.buf.len() - cmp::min(self.offset, self.buf.len())) / 32;
+        (remaining, Some(remaining))
+    }
+}
+
 impl Dir {
     /// Finds the entry named `name` in `self` and returns it. Comparison is
     /// case-insensitive.
@@ -133,14 +269,15 @@ impl Dir {
     /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
     /// is returned.
     pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
-        use traits::{Dir, Entry};
+        use traits::Entry;
         match name.as_ref().to_str() {
             None => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "File name contains non unicode charaters.",
             )),
             Some(name) => {
-                for entry in self.entries()? {
+                for entry in self.entries_streaming()? {
+                    let entry = entry?;
                     if entry.name().eq_ignore_ascii_case(name) {
                         return Ok(entry);
                     }
@@ -152,6 +289,156 @@ impl Dir {
             }
         }
     }
+
+    /// Like `entries`, but reads the directory one cluster at a time, as the
+    /// iterator reaches it, rather than all at once: no cluster past the
+    /// end-of-directory marker, or past the point where the iterator is
+    /// dropped, is read. An error reading a cluster is yielded in place of its
+    /// entries and ends the iteration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file system is in use, or, if
+    /// `VfatConfig::strict_dirs` is set, if the directory has no cluster.
+    pub fn entries_streaming(&self) -> io::Result<StreamingEntryIter> {
+        let (max_entries, codec, keep_raw_names) = {
+            let vfat = self.vfat.try_borrow_mut()?;
+            if self.first_cluster.inner() < 2 && vfat.config.strict_dirs {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Directory has no cluster chain.",
+                ));
+            }
+            let config = &vfat.config;
+            (config.max_dir_entries, config.oem_codec.clone(), config.keep_raw_names)
+        };
+        let slots = ClusterSlots {
+            vfat: self.vfat.clone(),
+            next: if self.first_cluster.inner() < 2 { None } else { Some(self.first_cluster) },
+            seen: HashSet::new(),
+            slots: RawSlots::new(Vec::new()),
+            used: 0,
+            max_entries,
+            error: None,
+        };
+        let mut parser = DirEntryParser::with_codec(slots, codec);
+        parser.keep_raw_names(keep_raw_names);
+        Ok(StreamingEntryIter {
+            parser,
+            dir: self.first_cluster,
+            vfat: self.vfat.clone(),
+        })
+    }
+
+    /// Returns the subdirectories of the directory, in directory order. Files,
+    /// `.`, `..` and entries carrying the volume ID attribute are left out.
+    pub fn subdirectories(&self) -> io::Result<Vec<Dir>> {
+        use traits::Dir;
+        Ok(self.entries()?
+            .filter_map(|entry| match entry {
+                Entry::Dir(dir) => Some(dir),
+                Entry::File(_) => None,
+            })
+            .filter(|dir| {
+                dir.name != "." && dir.name != ".." && !dir.metadata.attributes.volume_id()
+            })
+            .collect())
+    }
+
+    /// Returns the `index`th 32-byte slot of the directory exactly as stored,
+    /// whether it holds a regular, LFN, deleted or end-of-directory entry.
+    /// Returns `None` if `index` is beyond the last slot of the directory's
+    /// clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory's cluster chain is corrupt or can't
+    /// be read.
+    pub fn entry_at(&self, index: usize) -> io::Result<Option<RawDirEntry>> {
+        if self.first_cluster.inner() < 2 {
+            return Ok(None);
+        }
+        let mut vfat = self.vfat.try_borrow_mut()?;
+        let offset = index * mem::size_of::<RawDirEntry>();
+        let cluster_size = vfat.cluster_size();
+        let cluster = match vfat.chain(self.first_cluster).nth(offset / cluster_size) {
+            Some(cluster) => cluster?,
+            None => return Ok(None),
+        };
+        let mut bytes = [0u8; 32];
+        vfat.read_cluster(cluster, offset % cluster_size, &mut bytes)?;
+        Ok(Some(RawDirEntry { bytes }))
+    }
+
+    /// Checks that a path can be resolved through `self`: volume labels (and
+    /// so LFN-patterned entries) are never real directories, whatever else is
+    /// set, so descending into one is an error of `InvalidData`.
+    pub(crate) fn validate_traversable(&self) -> io::Result<()> {
+        if self.metadata.attributes.volume_id() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Entry marked as a directory is a volume label.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Opens the entry at the relative path `rel`, resolved starting from
+    /// `self` rather than from the root. `.` components are ignored, and `..`
+    /// returns to the directory before it in `rel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `rel` is absolute, if a `..`
+    /// would leave `self`, or if a component other than the last is a file.
+    /// Returns an error of `NotFound` if a component does not exist, and one
+    /// of `InvalidData` if a component other than the last is a volume label.
+    pub fn open<P: AsRef<Path>>(&self, rel: P) -> io::Result<Entry> {
+        // The directories resolved so far, `self` first.
+        let mut dirs = vec![self.clone()];
+        let mut components = rel.as_ref().components().peekable();
+        while let Some(component) = components.next() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    if dirs.len() == 1 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Relative path escapes the directory it is resolved from.",
+                        ));
+                    }
+                    dirs.pop();
+                }
+                Component::Normal(name) => {
+                    match dirs.last().unwrap().find(name)? {
+                        Entry::Dir(dir) => {
+                            if components.peek().is_some() {
+                                dir.validate_traversable()?;
+                            }
+                            dirs.push(dir)
+                        }
+                        Entry::File(file) => {
+                            if components.peek().is_some() {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::InvalidInput,
+                                    "A Component of Path is not a directory.",
+                                ));
+                            }
+                            file.validate_for_open()?;
+                            return Ok(Entry::File(file));
+                        }
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Path should be relative.",
+                    ))
+                }
+            }
+        }
+        Ok(Entry::Dir(dirs.pop().unwrap()))
+    }
 }
 
 impl traits::Dir for Dir {
@@ -164,128 +451,540 @@ impl traits::Dir for Dir {
     /// Returns an interator over the entries in this directory.
     fn entries(&self) -> io::Result<Self::Iter> {
         let mut buf = Vec::new();
-        self.vfat.borrow_mut().read_chain(
-            self.first_cluster,
-            &mut buf,
-        )?;
-        let raw_entries: Vec<VFatDirEntry> = unsafe { buf.cast() }; // TODO: works or not?
-        Ok(EntryIter::new(raw_entries.into_iter(), self.vfat.clone()))
+        let (max_entries, codec, keep_raw_names) = {
+            let mut vfat = self.vfat.try_borrow_mut()?;
+            // Every directory but an empty FAT12/16 root has at least one
+            // cluster, so a directory without one is corrupt.
+            if self.first_cluster.inner() < 2 {
+                if vfat.config.strict_dirs {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Directory has no cluster chain.",
+                    ));
+                }
+                let codec = vfat.config.oem_codec.clone();
+                let slots = RawSlots::new(Vec::new());
+                return Ok(EntryIter::new(slots, codec, self.first_cluster, self.vfat.clone()));
+            }
+            vfat.read_dir_chain(self.first_cluster, &mut buf)?;
+            let config = &vfat.config;
+            (config.max_dir_entries, config.oem_codec.clone(), config.keep_raw_names)
+        };
+        let slots = buf.chunks(32).take_while(|slot| slot[0] != 0x00).count();
+        if slots > max_entries {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Directory holds more entries than allowed.",
+            ));
+        }
+        let raw_entries = RawSlots::new(buf);
+        let mut iter = EntryIter::new(raw_entries, codec, self.first_cluster, self.vfat.clone());
+        iter.parser.keep_raw_names(keep_raw_names);
+        Ok(iter)
+    }
+}
+
+/// A 32-byte directory slot exactly as stored. Returned by `Dir::entry_at`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RawDirEntry {
+    bytes: [u8; 32],
+}
+
+impl RawDirEntry {
+    /// The raw bytes of the slot.
+    pub fn bytes(&self) -> &[u8; 32] {
+        &self.bytes
+    }
+
+    /// The attributes byte of the slot. For an LFN slot, `lfn()` is set.
+    pub fn attributes(&self) -> Attributes {
+        Attributes::from(self.bytes[11])
+    }
+
+    /// Whether the slot marks the end of the directory.
+    pub fn is_end(&self) -> bool {
+        self.bytes[0] == 0x00
+    }
+
+    /// Whether the slot holds a deleted entry.
+    pub fn is_deleted(&self) -> bool {
+        self.bytes[0] == 0xE5
+    }
+
+    /// Whether the slot holds part of a long file name.
+    pub fn is_lfn(&self) -> bool {
+        !self.is_end() && !self.is_deleted() && self.attributes().lfn()
     }
 }
 
+/// An iterator over the entries of a `Dir`.
 pub struct EntryIter {
-    raw_entries: vec::IntoIter<VFatDirEntry>,
+    parser: DirEntryParser<RawSlots>,
+    /// The first cluster of the directory being listed.
+    dir: Cluster,
     vfat: Shared<VFat>,
-    lfn: Option<[[u16; 13]; 0x1F]>,
 }
 
 impl EntryIter {
-    fn new(raw_entries: vec::IntoIter<VFatDirEntry>, vfat: Shared<VFat>) -> EntryIter {
+    fn new(
+        raw_entries: RawSlots,
+        codec: OemCodec,
+        dir: Cluster,
+        vfat: Shared<VFat>,
+    ) -> EntryIter {
         EntryIter {
-            raw_entries,
+            parser: DirEntryParser::with_codec(raw_entries, codec),
+            dir,
             vfat,
-            lfn: None,
         }
     }
+
+    /// The number of runs of LFN entries skipped so far because no regular
+    /// entry followed them, as left behind by an interrupted write. Such runs
+    /// are otherwise dropped silently; check this once the iterator is done
+    /// to find out whether the directory needs repair.
+    pub fn orphaned_lfn_runs(&self) -> usize {
+        self.parser.orphaned_lfn_runs()
+    }
+}
+
+/// Wraps `entry`, parsed from the whole of the directory of `vfat` starting
+/// at cluster `dir`, as an `Entry`.
+pub(crate) fn make_entry(entry: ParsedEntry, dir: Cluster, vfat: &Shared<VFat>) -> Entry {
+    let entry_slot = EntrySlot { dir, index: entry.slot };
+    if entry.metadata.attributes.directory() {
+        // `..` in a directory just below the root records the root as
+        // cluster 0.
+        let first_cluster = if entry.first_cluster.inner() == 0 && entry.name == ".." {
+            vfat.borrow().root_dir_cluster
+        } else {
+            entry.first_cluster
+        };
+        let mut dir = Dir::new(
+            entry.name,
+            entry.short_name,
+            entry.metadata,
+            first_cluster,
+            vfat.clone(),
+        );
+        dir.raw_name = entry.raw_name;
+        dir.entry_slot = Some(entry_slot);
+        Entry::Dir(dir)
+    } else {
+        Entry::File(File::new(
+            entry.name,
+            entry.short_name,
+            entry.metadata,
+            entry.size,
+            entry.first_cluster,
+            vfat.clone(),
+        ).with_raw_name(entry.raw_name).with_entry_slot(entry_slot))
+    }
 }
 
 impl iter::Iterator for EntryIter {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.raw_entries.next().and_then(|raw_entry: VFatDirEntry| {
+        let entry = self.parser.next()?;
+        Some(make_entry(entry, self.dir, &self.vfat))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.parser.size_hint()
+    }
+}
+
+impl iter::FusedIterator for EntryIter {}
+
+/// The raw entries of a directory, read one cluster at a time as they are
+/// needed. An error ends the entries and is kept in `error`.
+struct ClusterSlots {
+    vfat: Shared<VFat>,
+    /// The cluster to read once `slots` runs out.
+    next: Option<Cluster>,
+    seen: HashSet<Cluster>,
+    slots: RawSlots,
+    /// The number of slots other than the end marker yielded so far.
+    used: usize,
+    max_entries: usize,
+    error: Option<io::Error>,
+}
+
+impl ClusterSlots {
+    /// Reads the next cluster of the directory into `slots`, returning
+    /// `false` if there is none.
+    fn read_next_cluster(&mut self) -> io::Result<bool> {
+        let cluster = match self.next.take() {
+            Some(cluster) => cluster,
+            None => return Ok(false),
+        };
+        if !self.seen.insert(cluster) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Cluster chain is cross-linked or cyclic.",
+            ));
+        }
+        let mut vfat = self.vfat.try_borrow_mut()?;
+        let mut buf = vec![0; vfat.cluster_size()];
+        vfat.read_cluster(cluster, 0, &mut buf)?;
+        self.next = vfat.next_cluster(cluster)?;
+        self.slots = RawSlots::new(buf);
+        Ok(true)
+    }
+}
+
+impl iter::Iterator for ClusterSlots {
+    type Item = VFatDirEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        loop {
+            if let Some(slot) = self.slots.next() {
+                if unsafe { slot.unknown }.seq_num != 0x00 {
+                    self.used += 1;
+                    if self.used > self.max_entries {
+                        self.error = Some(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Directory holds more entries than allowed.",
+                        ));
+                        return None;
+                    }
+                }
+                return Some(slot);
+            }
+            match self.read_next_cluster() {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(e) => {
+                    self.error = Some(e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the entries of a `Dir` that reads the directory as it
+/// goes. Returned by `Dir::entries_streaming`.
+pub struct StreamingEntryIter {
+    parser: DirEntryParser<ClusterSlots>,
+    /// The first cluster of the directory being listed.
+    dir: Cluster,
+    vfat: Shared<VFat>,
+}
+
+impl StreamingEntryIter {
+    /// Like `EntryIter::orphaned_lfn_runs`, for the part of the directory
+    /// read so far.
+    pub fn orphaned_lfn_runs(&self) -> usize {
+        self.parser.orphaned_lfn_runs()
+    }
+}
+
+impl iter::Iterator for StreamingEntryIter {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.parser.next() {
+            Some(entry) => Some(Ok(make_entry(entry, self.dir, &self.vfat))),
+            None => self.parser.raw_entries.error.take().map(Err),
+        }
+    }
+}
+
+/// A directory entry as decoded by `DirEntryParser`, not yet tied to a file
+/// system.
+#[derive(Debug, Clone)]
+pub struct ParsedEntry {
+    /// The long file name if there is one, otherwise the same as `short_name`.
+    pub name: String,
+    pub short_name: String,
+    pub metadata: Metadata,
+    pub first_cluster: Cluster,
+    pub size: u32,
+    /// The index of the entry's 8.3 slot among the raw entries parsed, and
+    /// so within its directory if the whole directory was parsed.
+    pub slot: usize,
+    /// The name bytes as stored, laid out as for `File::raw_name_bytes`.
+    /// Empty unless the parser keeps raw names.
+    pub raw_name: Vec<u8>,
+}
+
+/// Decodes raw directory entries into `ParsedEntry`s: LFN entries are joined
+/// onto the regular entry following them, deleted entries are skipped, and
+/// parsing stops at the end-of-directory marker. No I/O is involved.
+///
+/// A run of LFN entries that is not followed by its regular entry (left
+/// behind by an interrupted write, say) is discarded and counted; see
+/// `orphaned_lfn_runs`.
+///
+/// Once `next` has returned `None`, it keeps returning `None`.
+pub struct DirEntryParser<I> {
+    raw_entries: I,
+    codec: OemCodec,
+    lfn: Option<[[u16; 13]; 0x1F]>,
+    orphaned_lfn_runs: usize,
+    /// The number of raw entries taken from `raw_entries` so far.
+    slots_read: usize,
+    keep_raw_names: bool,
+    /// Set once the end-of-directory marker or the last raw entry is reached.
+    done: bool,
+}
+
+impl<I: Iterator<Item = VFatDirEntry>> DirEntryParser<I> {
+    /// Creates a parser decoding short names as code page 437.
+    pub fn new(raw_entries: I) -> DirEntryParser<I> {
+        DirEntryParser::with_codec(raw_entries, OemCodec::cp437())
+    }
+
+    /// Creates a parser decoding short names with `codec`.
+    pub fn with_codec(raw_entries: I, codec: OemCodec) -> DirEntryParser<I> {
+        DirEntryParser {
+            raw_entries,
+            codec,
+            lfn: None,
+            orphaned_lfn_runs: 0,
+            slots_read: 0,
+            keep_raw_names: false,
+            done: false,
+        }
+    }
+
+    /// Sets whether parsed entries carry their raw name bytes in
+    /// `ParsedEntry::raw_name`. Off by default.
+    pub fn keep_raw_names(&mut self, keep: bool) {
+        self.keep_raw_names = keep;
+    }
+
+    /// The number of runs of LFN entries discarded so far because no regular
+    /// entry followed them.
+    pub fn orphaned_lfn_runs(&self) -> usize {
+        self.orphaned_lfn_runs
+    }
+}
+
+impl DirEntryParser<vec::IntoIter<VFatDirEntry>> {
+    /// Creates a parser over the 32-byte entries in `bytes`. A trailing
+    /// partial entry is ignored.
+    pub fn from_bytes(bytes: &[u8]) -> DirEntryParser<vec::IntoIter<VFatDirEntry>> {
+        DirEntryParser::from_bytes_with_codec(bytes, OemCodec::cp437())
+    }
+
+    /// Like `from_bytes`, but decodes short names with `codec`.
+    pub fn from_bytes_with_codec(
+        bytes: &[u8],
+        codec: OemCodec,
+    ) -> DirEntryParser<vec::IntoIter<VFatDirEntry>> {
+        let raw_entries: Vec<VFatDirEntry> = bytes
+            .chunks(mem::size_of::<VFatDirEntry>())
+            .filter(|chunk| chunk.len() == mem::size_of::<VFatDirEntry>())
+            .map(VFatDirEntry::decode)
+            .collect();
+        DirEntryParser::with_codec(raw_entries.into_iter(), codec)
+    }
+}
+
+impl<I: Iterator<Item = VFatDirEntry>> iter::Iterator for DirEntryParser<I> {
+    type Item = ParsedEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let raw_entry = match self.raw_entries.next() {
+                Some(raw_entry) => {
+                    self.slots_read += 1;
+                    raw_entry
+                }
+                None => {
+                    self.discard_lfn();
+                    self.done = true;
+                    return None;
+                }
+            };
             let entry = unsafe { raw_entry.unknown };
             match entry.seq_num {
-                0x00 => None,        // the previous entry was the last entry
-                0xE5 => self.next(), // this is a deleted/unused entry; TODO: should lfn be cleared?
-                raw_seq_num => {
-                    if entry.attributes.lfn() {
-                        // VFatLfnDirEntry
-                        let seq_num = raw_seq_num & 0b00011111; // Only bits 0-4 is seq num.
-                        if !(seq_num >= 0x01 && seq_num <= 0x1F) {
-                            // invalid seq_num
-                            panic!("Unexpected sequence number: {}.", seq_num);
-                        }
-                        let entry = unsafe { raw_entry.long_filename };
-                        {
-                            let lfn = self.lfn.get_or_insert([[0x0000; 13]; 0x1F]);
-                            let lfn = &mut lfn[(seq_num - 1) as usize];
-                            lfn[0..5].copy_from_slice(&entry.name_characters_1);
-                            lfn[5..11].copy_from_slice(&entry.name_characters_2);
-                            lfn[11..13].copy_from_slice(&entry.name_characters_3);
-                        }
-                        self.next()
-                    } else {
-                        let entry = unsafe { raw_entry.regular };
-                        let mut file_name = match self.lfn {
-                            Some(ref lfn) => {
-                                let raw_lfn: Vec<u16> = lfn
-                                    .into_iter()
-                                    .flat_map(|e| e)
-                                    .map(|c| *c)
-                                    .take_while(|&c| c != 0x0000 && c != 0xFFFF) // TODO: right?
-                                    .collect();
-                                // u16 is required here and in any other related place!
-                                // Vec<u8> cannot be casted to Vec<u16> due to alignment issues.
-                                // let raw_lfn: Vec<u16> = unsafe { raw_lfn.cast() };
-                                String::from_utf16_lossy(raw_lfn.as_slice())
-                            }
-                            None => {
-                                // It seems that: When there is LFN,
-                                // the regular file name should be ignored regardlessly.
-                                let name: Vec<u8> = entry
-                                    .name
-                                    .iter()
-                                    .map(|c| *c)
-                                    .take_while(|&c| c != 0x00 && c != 0x20)
-                                    .collect();
-                                let mut file_name = String::from_utf8_lossy(&name).into_owned();
-                                let extension: Vec<u8> = entry
-                                    .extension
-                                    .iter()
-                                    .map(|c| *c)
-                                    .take_while(|&c| c != 0x00 && c != 0x20)
-                                    .collect();
-                                if !extension.is_empty() {
-                                    file_name.push_str(".");
-                                    file_name.push_str({
-                                        &String::from_utf8_lossy(&extension)
-                                    });
-                                }
-                                file_name
-                            }
-                        };
-                        self.lfn = None; // clear lfn
-
-                        let metadata = Metadata {
-                            attributes: entry.attributes,
-                            created_time: (entry.cdate, entry.ctime).into(),
-                            accessed_time: (entry.adate, 0.into()).into(),
-                            modified_time: (entry.mdate, entry.mtime).into(),
-                        };
-
-                        let first_cluster = (((entry.first_cluster_higher_bits as u32) << 16) |
-                                                 entry.first_cluster_lower_bits as u32)
-                            .into();
-                        Some(if metadata.attributes.directory() {
-                            Entry::Dir(Dir::new(
-                                file_name,
-                                metadata,
-                                first_cluster,
-                                self.vfat.clone(),
-                            ))
-                        } else {
-                            Entry::File(File::new(
-                                file_name,
-                                metadata,
-                                entry.size,
-                                first_cluster,
-                                self.vfat.clone(),
-                            ))
-                        })
+                0x00 => {
+                    // the previous entry was the last entry
+                    self.discard_lfn();
+                    self.done = true;
+                    return None;
+                }
+                0xE5 => {
+                    // this is a deleted/unused entry; it interrupts any LFN run
+                    self.discard_lfn();
+                    continue;
+                }
+                raw_seq_num if entry.attributes.lfn() => {
+                    // VFatLfnDirEntry
+                    let seq_num = raw_seq_num & 0b00011111; // Only bits 0-4 is seq num.
+                    if !(seq_num >= 0x01 && seq_num <= 0x1F) {
+                        // invalid seq_num: the name it belongs to is unusable
+                        self.discard_lfn();
+                        continue;
                     }
+                    if raw_seq_num & 0x40 != 0 {
+                        // the physically first entry of a new run
+                        self.discard_lfn();
+                    }
+                    let entry = unsafe { raw_entry.long_filename };
+                    let lfn = self.lfn.get_or_insert([[0x0000; 13]; 0x1F]);
+                    let lfn = &mut lfn[(seq_num - 1) as usize];
+                    lfn[0..5].copy_from_slice(&entry.name_characters_1);
+                    lfn[5..11].copy_from_slice(&entry.name_characters_2);
+                    lfn[11..13].copy_from_slice(&entry.name_characters_3);
+                }
+                _ => {
+                    let entry = unsafe { raw_entry.regular };
+                    return Some(self.finish(&entry));
                 }
             }
-        })
+        }
+    }
+
+    /// Every entry yielded consumes at least one raw entry, so the raw
+    /// entries remaining bound the entries remaining.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.done {
+            (0, Some(0))
+        } else {
+            (0, self.raw_entries.size_hint().1)
+        }
+    }
+}
+
+impl<I: Iterator<Item = VFatDirEntry>> iter::FusedIterator for DirEntryParser<I> {}
+
+impl<I> DirEntryParser<I> {
+    /// Drops the LFN entries collected so far, if any, as an orphaned run.
+    fn discard_lfn(&mut self) {
+        if self.lfn.take().is_some() {
+            self.orphaned_lfn_runs += 1;
+        }
+    }
+
+    /// Builds the entry for the regular entry `entry`, consuming the LFN
+    /// collected before it.
+    fn finish(&mut self, entry: &VFatRegularDirEntry) -> ParsedEntry {
+        let short_name = decode_short_name(entry, &self.codec);
+        let raw_name = if self.keep_raw_names {
+            self.raw_name(entry)
+        } else {
+            Vec::new()
+        };
+        // It seems that: When there is LFN,
+        // the regular file name should be ignored regardlessly.
+        let name = match self.lfn.take() {
+            Some(ref lfn) => {
+                let raw_lfn: Vec<u16> = lfn
+                    .into_iter()
+                    .flat_map(|e| e)
+                    .map(|c| *c)
+                    .take_while(|&c| c != 0x0000 && c != 0xFFFF) // TODO: right?
+                    .collect();
+                // u16 is required here and in any other related place!
+                // Vec<u8> cannot be casted to Vec<u16> due to alignment issues.
+                String::from_utf16_lossy(raw_lfn.as_slice())
+            }
+            None => short_name.clone(),
+        };
+
+        let metadata = Metadata {
+            attributes: entry.attributes,
+            created_time: (entry.cdate, entry.ctime).into(),
+            accessed_time: (entry.adate, 0.into()).into(),
+            modified_time: (entry.mdate, entry.mtime).into(),
+        };
+        let first_cluster = (((entry.first_cluster_higher_bits as u32) << 16) |
+                                 entry.first_cluster_lower_bits as u32)
+            .into();
+        ParsedEntry {
+            name,
+            short_name,
+            metadata,
+            first_cluster,
+            size: entry.size,
+            slot: self.slots_read - 1,
+            raw_name,
+        }
+    }
+
+    /// Collects the raw name bytes of `entry` and the LFN entries before it.
+    fn raw_name(&self, entry: &VFatRegularDirEntry) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(11);
+        raw.extend_from_slice(&{ entry.name });
+        raw.extend_from_slice(&{ entry.extension });
+        if let Some(ref lfn) = self.lfn {
+            // Slots past the highest sequence number seen are never written.
+            let used = lfn.iter().rposition(|part| part.iter().any(|&c| c != 0)).map_or(0, |i| i + 1);
+            for &c in lfn[..used].iter().flat_map(|part| part.iter()) {
+                raw.push(c as u8);
+                raw.push((c >> 8) as u8);
+            }
+        }
+        raw
+    }
+}
+
+/// Checks that `name` can be stored as a long file name: it must be between 1
+/// and 255 UTF-16 code units long, must not be `.` or `..`, and must contain
+/// neither control characters nor any of `/ \ : * ? " < > |`.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `name` is not a valid name.
+pub fn validate_filename(name: &str) -> io::Result<()> {
+    let units = name.encode_utf16().count();
+    if units == 0 || units > 255 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "File name must be between 1 and 255 UTF-16 code units long.",
+        ));
+    }
+    if name == "." || name == ".." ||
+        name.chars().any(|c| c.is_control() || "/\\:*?\"<>|".contains(c))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "File name contains an invalid character.",
+        ));
+    }
+    Ok(())
+}
+
+/// Computes the checksum of the 11-byte short name `name_11` (8 name bytes,
+/// then 3 extension bytes, space padded) that every LFN entry belonging to it
+/// records.
+pub fn sfn_checksum(name_11: &[u8; 11]) -> u8 {
+    name_11.iter().fold(0u8, |sum, &c| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c)
+    })
+}
+
+/// Decodes the 8.3 name of `entry` with `codec` into `NAME.EXT` form, omitting
+/// the dot when there is no extension.
+fn decode_short_name(entry: &VFatRegularDirEntry, codec: &OemCodec) -> String {
+    let decode = |bytes: &[u8]| -> String {
+        bytes
+            .iter()
+            .take_while(|&&c| c != 0x00 && c != 0x20)
+            .map(|&c| codec.decode(c))
+            .collect()
+    };
+    let mut name = entry.name;
+    // A leading 0xE5 is stored as 0x05, as 0xE5 marks deleted entries.
+    if name[0] == 0x05 {
+        name[0] = 0xE5;
+    }
+    let mut file_name = decode(&name);
+    let extension = decode(&entry.extension);
+    if !extension.is_empty() {
+        file_name.push_str(".");
+        file_name.push_str(&extension);
     }
+    file_name
 }
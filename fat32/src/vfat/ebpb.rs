@@ -1,7 +1,15 @@
-use std::{io, fmt, mem, str};
+use std::{cmp, fmt, mem, str};
 
 use traits::BlockDevice;
-use vfat::Error;
+use vfat::{Error, OemCodec};
+
+/// The width of the entries of a file allocation table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
 
 #[repr(C, packed)]
 pub struct BiosParameterBlock {
@@ -44,15 +52,37 @@ impl BiosParameterBlock {
     ///
     /// # Errors
     ///
-    /// If the EBPB signature is invalid, returns an error of `BadSignature`.
-    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<BiosParameterBlock, Error> {
-        let mut buf = [0u8; 512];
-        if device.read_sector(sector, &mut buf)? != 512 {
-            return Err(Error::Io(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Got less than 512 bytes when reading MBR.",
-            )));
+    /// If the EBPB signature is invalid, returns an error of `BadSignature`. If
+    /// the FSInfo or backup boot sector lies outside of the reserved region,
+    /// returns an error of `InvalidBpb`.
+    pub fn from<T: BlockDevice>(device: T, sector: u64) -> Result<BiosParameterBlock, Error> {
+        let bpb = BiosParameterBlock::read(device, sector)?;
+        let reserved = bpb.number_of_reserved_sectors;
+        let in_reserved = |sector: u16| sector == 0 || sector == 0xFFFF || sector < reserved;
+        if !in_reserved(bpb.fsinfo_sector()) {
+            return Err(Error::InvalidBpb("FSInfo sector is outside of the reserved region."));
         }
+        if !in_reserved(bpb.backup_boot_sector()) {
+            return Err(Error::InvalidBpb(
+                "Backup boot sector is outside of the reserved region.",
+            ));
+        }
+        Ok(bpb)
+    }
+
+    /// Reads a boot sector from sector `sector` of device `device`, checking
+    /// only its signature at offset 510. The FAT32-specific fields are meaningless if the
+    /// volume is FAT12 or FAT16.
+    pub(crate) fn read<T: BlockDevice>(
+        mut device: T,
+        sector: u64,
+    ) -> Result<BiosParameterBlock, Error> {
+        // Devices with sectors larger than 512 bytes may refuse to read less
+        // than a whole sector; the BPB is the first 512 bytes of it.
+        let mut sector_buf = vec![0u8; cmp::max(512, device.sector_size() as usize)];
+        device.read_sector_exact(sector, &mut sector_buf)?;
+        let mut buf = [0u8; 512];
+        buf.copy_from_slice(&sector_buf[..512]);
         let bpb = unsafe { mem::transmute::<[u8; 512], BiosParameterBlock>(buf) };
         /* if (bpb.signature >> 1) != (0x28 >> 1) {
             return Err(Error::BadSignature);
@@ -62,6 +92,79 @@ impl BiosParameterBlock {
         }
         Ok(bpb)
     }
+
+    /// Determines the FAT width from the number of data clusters, the only
+    /// method the specification allows. Returns `None` if the geometry is too
+    /// broken to count clusters.
+    pub fn fat_type(&self) -> Option<FatType> {
+        let bytes_per_sector = self.bytes_per_sector as u32;
+        if bytes_per_sector == 0 || self.sectors_per_cluster == 0 {
+            return None;
+        }
+        let root_dir_sectors =
+            (self.max_no_of_director_entries as u32 * 32 + bytes_per_sector - 1) / bytes_per_sector;
+        let sectors_per_fat = match self._number_of_sectors_per_fat {
+            0 => self.sectors_per_fat,
+            n => n as u32,
+        };
+        let metadata_sectors = self.number_of_reserved_sectors as u64 +
+            self.number_of_fats as u64 * sectors_per_fat as u64 + root_dir_sectors as u64;
+        let data_sectors = (self.total_sectors() as u64).checked_sub(metadata_sectors)?;
+        Some(match data_sectors / self.sectors_per_cluster as u64 {
+            0...4084 => FatType::Fat12,
+            4085...65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        })
+    }
+
+    /// The volume label recorded in the boot sector, with trailing spaces
+    /// removed. `None` if there is no label, it is `NO NAME`, or the FAT width
+    /// can't be determined. The root directory may hold a different label;
+    /// see `VFat::volume_label`.
+    pub fn volume_label(&self) -> Option<String> {
+        // FAT12 and FAT16 keep the extended fields right after the BPB.
+        let (signature, label) = match self.fat_type()? {
+            FatType::Fat32 => (66, 71),
+            FatType::Fat12 | FatType::Fat16 => (38, 43),
+        };
+        let raw = unsafe { &*(self as *const BiosParameterBlock as *const [u8; 512]) };
+        if raw[signature] != 0x29 {
+            return None;
+        }
+        let label = &raw[label..label + 11];
+        let len = label.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+        if len == 0 || label == b"NO NAME    " {
+            return None;
+        }
+        let codec = OemCodec::cp437();
+        Some(label[..len].iter().map(|&b| codec.decode(b)).collect())
+    }
+
+    /// The number of sectors in the volume: the 32-bit count if it is set,
+    /// otherwise the 16-bit count used by small volumes.
+    pub fn total_sectors(&self) -> u32 {
+        match self.total_logical_sectors_ {
+            0 => self.total_logical_sectors as u32,
+            total => total,
+        }
+    }
+
+    /// A copy of the 420 bytes of boot code following the EBPB.
+    pub fn boot_code(&self) -> [u8; 420] {
+        self.boot_code
+    }
+
+    /// The sector of the backup copy of the boot sector, relative to the start
+    /// of the partition. 0 or 0xFFFF if there is no backup.
+    pub fn backup_boot_sector(&self) -> u16 {
+        self.sector_no_of_backup_boot_sector
+    }
+
+    /// The sector of the FSInfo structure, relative to the start of the
+    /// partition. 0 or 0xFFFF if there is no FSInfo structure.
+    pub fn fsinfo_sector(&self) -> u16 {
+        self.sector_no_of_fsinfo_structure
+    }
 }
 
 impl fmt::Debug for BiosParameterBlock {
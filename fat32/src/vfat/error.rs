@@ -8,6 +8,30 @@ pub enum Error {
     Io(io::Error),
     BadSignature,
     NotFound,
+    /// The EBPB describes an impossible volume layout.
+    InvalidBpb(&'static str),
+    /// The device has no FAT32 partition but is itself a FAT32 file system
+    /// without a partition table; mount it with `VFat::from_unpartitioned`.
+    Unpartitioned,
+    /// The device has no FAT32 partition, but does have a partition of another
+    /// type.
+    UnsupportedFs(mbr::UnsupportedFs),
+}
+
+/// The message of the error returned by operations that would modify the
+/// file system, none of which are supported without the `write` feature.
+const READ_ONLY: &str = "filesystem is read-only";
+
+/// Returns the error returned by operations that would modify the file
+/// system: an `io::Error` of kind `Other`.
+pub fn read_only_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, READ_ONLY)
+}
+
+/// Returns whether `error` was returned by `read_only_error`.
+pub fn is_read_only_error(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::Other &&
+        error.get_ref().map_or(false, |inner| inner.to_string() == READ_ONLY)
 }
 
 impl From<mbr::Error> for Error {
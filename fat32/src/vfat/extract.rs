@@ -0,0 +1,56 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
+
+use traits::{Entry, FileSystem};
+use vfat::{Shared, VFat};
+
+impl Shared<VFat> {
+    /// Copies the file or directory at `src` in the image to the host path
+    /// `dst` and returns the number of file bytes copied. A directory is
+    /// recreated recursively, with `dst` taking the place of `src`. Existing
+    /// host files are overwritten.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `src` can't be opened or a host file or directory
+    /// can't be created. Returns an error of `InvalidData` if an entry's name
+    /// would place it outside of `dst` on the host.
+    pub fn extract<P, Q>(&self, src: P, dst: Q) -> io::Result<u64>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        let mut file = match self.open(src)?.into_file() {
+            Some(file) => file,
+            None => return self.extract_dir(src, dst),
+        };
+        io::copy(&mut file, &mut fs::File::create(dst)?)
+    }
+
+    fn extract_dir(&self, src: &Path, dst: &Path) -> io::Result<u64> {
+        fs::create_dir_all(dst)?;
+        let mut copied = 0;
+        for entry in self.walk(src)? {
+            let entry = entry?;
+            let relative = entry.path.strip_prefix(src).unwrap_or(&entry.path);
+            let is_plain = |component: Component| match component {
+                Component::Normal(_) => true,
+                _ => false,
+            };
+            if !relative.components().all(is_plain) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Entry name is not a valid host file name.",
+                ));
+            }
+            let target = dst.join(relative);
+            match entry.entry.into_file() {
+                Some(mut file) => copied += io::copy(&mut file, &mut fs::File::create(target)?)?,
+                None => fs::create_dir_all(target)?,
+            }
+        }
+        Ok(copied)
+    }
+}
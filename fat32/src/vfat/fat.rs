@@ -21,10 +21,16 @@ pub enum Status {
 pub struct FatEntry(pub u32);
 
 impl FatEntry {
+    /// Returns the 28-bit value of the entry, without the reserved upper four
+    /// bits.
+    pub fn value(&self) -> u32 {
+        self.0 & !(0xF << 28)
+    }
+
     /// Returns the `Status` of the FAT entry `self`.
     pub fn status(&self) -> Status {
         use self::Status::*;
-        match self.0 & !(0xF << 28) { // ignore the upper 4 digits
+        match self.value() {
             0x0000000 => Free,
             0x0000001 => Reserved,
             v @ 0x0000002...0xFFFFFEF => Data(v.into()),
@@ -44,3 +50,11 @@ impl fmt::Debug for FatEntry {
             .finish()
     }
 }
+
+/// Formats the entry's value in hex, followed by its status, e.g.
+/// `0x0FFFFFFF (Eoc(268435455))`. The reserved upper four bits are left out.
+impl fmt::Display for FatEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#010X} ({:?})", self.value(), self.status())
+    }
+}
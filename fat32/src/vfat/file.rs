@@ -1,22 +1,59 @@
-use std::cmp::min;
-use std::io::{self, Seek, SeekFrom};
+#[cfg(feature = "write")]
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, SeekFrom};
+use std::vec;
 
 use traits;
 use vfat::{Cluster, Metadata, Shared, VFat};
+#[cfg(not(feature = "write"))]
+use vfat::read_only_error;
+use vfat::dir::EntrySlot;
 
+/// A file in a `VFat` file system.
+///
+/// Cloning a `File` is cheap: the clone shares the file system (and so its
+/// sector cache) with the original but has its own cursor, starting at the
+/// original's current position. With write support, bytes the original holds
+/// in its buffer (see `VfatConfig::write_buffer_size`) are written to the
+/// volume first, so the clone reads them; if that fails, the original keeps
+/// them and reports the error on its next `flush`.
 #[derive(Debug)]
+#[cfg_attr(not(feature = "write"), derive(Clone))]
 pub struct File {
     pub name: String,
+    short_name: String,
     pub metadata: Metadata,
     pub size: u32,
     first_cluster: Cluster,
     vfat: Shared<VFat>,
     offset: u32,
+    /// The index in the chain and number of the cluster last read, so reads
+    /// moving on through the file look up one FAT entry per cluster boundary
+    /// instead of walking the chain from the start. Reset by `seek`.
+    cursor: Option<(u32, Cluster)>,
+    raw_name: Vec<u8>,
+    /// Where the file's size and first cluster are recorded, if the file was
+    /// found in a directory.
+    entry_slot: Option<EntrySlot>,
+    #[cfg(feature = "write")]
+    buffer: RefCell<WriteBuffer>,
+}
+
+/// Written bytes a `File` holds back until they are written to the volume.
+/// The clusters they belong in are already allocated.
+#[cfg(feature = "write")]
+#[derive(Debug, Default)]
+struct WriteBuffer {
+    /// The position in the file the held bytes start at.
+    start: u32,
+    data: Vec<u8>,
 }
 
 impl File {
     pub fn new(
         name: String,
+        short_name: String,
         metadata: Metadata,
         size: u32,
         first_cluster: Cluster,
@@ -24,12 +61,342 @@ impl File {
     ) -> File {
         File {
             name,
+            short_name,
             metadata,
             size,
             first_cluster,
             vfat,
             offset: 0,
+            cursor: None,
+            raw_name: Vec::new(),
+            entry_slot: None,
+            #[cfg(feature = "write")]
+            buffer: RefCell::new(WriteBuffer::default()),
+        }
+    }
+
+    pub(crate) fn with_raw_name(mut self, raw_name: Vec<u8>) -> File {
+        self.raw_name = raw_name;
+        self
+    }
+
+    pub(crate) fn with_entry_slot(mut self, entry_slot: EntrySlot) -> File {
+        self.entry_slot = Some(entry_slot);
+        self
+    }
+
+    #[cfg(any(feature = "write", feature = "fuse"))]
+    pub(crate) fn entry_slot(&self) -> Option<EntrySlot> {
+        self.entry_slot
+    }
+
+    /// The 8.3 short name of the file. It is the same as `name` unless the file
+    /// has a long file name.
+    pub fn short_name(&self) -> String {
+        self.short_name.clone()
+    }
+
+    /// The first cluster of the file, as accepted by `VFat::path_of`. It is
+    /// cluster 0 for an empty file, which `path_of` can't resolve.
+    pub fn first_cluster(&self) -> Cluster {
+        self.first_cluster
+    }
+
+    /// The name bytes exactly as stored: the 11 bytes of the 8.3 entry's name
+    /// and extension, followed by the UTF-16LE code units of every LFN entry
+    /// (terminator and padding included), in name order. Empty unless
+    /// `VfatConfig::keep_raw_names` was set when the file was listed.
+    pub fn raw_name_bytes(&self) -> &[u8] {
+        &self.raw_name
+    }
+
+    /// Returns the current position in the file. Unlike the `Seek`-provided
+    /// equivalent, this never seeks.
+    pub fn stream_position(&self) -> u64 {
+        self.offset as u64
+    }
+
+    /// Returns the length of the file in bytes. Unlike the `Seek`-provided
+    /// equivalent, this never seeks.
+    pub fn stream_len(&self) -> u64 {
+        self.size as u64
+    }
+
+    /// Clears `buf` and fills it with the rest of the file, from the current
+    /// position to the end, following the file's cluster chain. The capacity
+    /// of `buf` is reused, so one buffer can serve many files. On success the
+    /// position is at the end of the file and the number of bytes read is
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `UnexpectedEof` if the cluster chain ends before
+    /// the file does, or of `InvalidData` if the file is larger than
+    /// `VfatConfig::max_file_size`.
+    pub fn read_all_into(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        #[cfg(feature = "write")]
+        self.flush_buffer()?;
+        buf.clear();
+        let remaining = (self.size - self.offset) as usize;
+        if remaining == 0 {
+            return Ok(0);
+        }
+        {
+            let mut vfat = self.vfat.try_borrow_mut()?;
+            check_max_file_size(&vfat, self.size as u64)?;
+            let cluster_size = vfat.cluster_size();
+            let clusters = vfat.chain(self.first_cluster).collect::<io::Result<Vec<_>>>()?;
+            let mut offset_in_cluster = self.offset as usize % cluster_size;
+            let first = self.offset as usize / cluster_size;
+
+            buf.resize(remaining, 0);
+            let mut read = 0;
+            for &cluster in clusters.iter().skip(first) {
+                if read == remaining {
+                    break;
+                }
+                let limit = offset_in_cluster + (remaining - read);
+                read += vfat.read_cluster_bounded(cluster, offset_in_cluster, limit, &mut buf[read..])?;
+                offset_in_cluster = 0;
+            }
+            if read < remaining {
+                buf.truncate(read);
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Cluster chain ends before the file does.",
+                ));
+            }
+        }
+        self.offset = self.size;
+        Ok(remaining)
+    }
+
+    /// Reads the file's `index`th cluster (0-indexed, in chain order) into
+    /// `buf`, stopping at the end of the file or of `buf`, and returns the
+    /// number of bytes read. The file's position is neither used nor moved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if the file has fewer than
+    /// `index + 1` clusters' worth of data, or of `UnexpectedEof` if the
+    /// cluster chain ends before the file does.
+    pub fn read_nth_cluster(&mut self, index: u32, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "write")]
+        self.flush_buffer()?;
+        let mut vfat = self.vfat.try_borrow_mut()?;
+        let cluster_size = vfat.cluster_size() as u64;
+        let start = index as u64 * cluster_size;
+        if start >= self.size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cluster index is beyond the end of the file.",
+            ));
         }
+        let cluster = nth_cluster(&mut vfat, self.first_cluster, &mut self.cursor, index)?;
+        let limit = cmp::min(cluster_size, self.size as u64 - start) as usize;
+        vfat.read_cluster_bounded(cluster, 0, limit, buf)
+    }
+
+    /// Returns an iterator over the sectors holding the file's contents, in
+    /// file order, as pairs of the sector number (numbered like
+    /// `VFat::cluster_start_sector`) and the sector's bytes. The last sector
+    /// is cut off at the end of the file. Only one sector is held at a time,
+    /// and the file's position is neither used nor moved.
+    ///
+    /// Each sector is copied out of the sector cache: the cache lives behind
+    /// the file system's shared handle, which is only borrowed for the
+    /// duration of each `next` call, so a reference into it could not outlive
+    /// that call. For the same reason, the file system must not be borrowed
+    /// elsewhere while `next` is called, or it yields an error.
+    ///
+    /// The iterator yields an error of `UnexpectedEof` and stops if the
+    /// cluster chain ends before the file does.
+    ///
+    /// With write support, bytes held in the file's buffer are written to
+    /// the volume first. Errors doing so can't be reported here: call `flush`
+    /// beforehand to see them.
+    pub fn sectors(&self) -> Sectors {
+        #[cfg(feature = "write")]
+        let _ = self.flush_buffer();
+        Sectors {
+            vfat: self.vfat.clone(),
+            first_cluster: self.first_cluster,
+            clusters: None,
+            sector: None,
+            remaining: self.size as u64,
+        }
+    }
+
+    /// Compares the number of clusters in the file's chain with the number its
+    /// size calls for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cluster chain is corrupt or can't be read.
+    pub fn verify_allocation(&self) -> io::Result<AllocStatus> {
+        let mut vfat = self.vfat.try_borrow_mut()?;
+        let allocated = vfat.chain_length(self.first_cluster)?;
+        let cluster_size = vfat.cluster_size() as u64;
+        let needed = ((self.size as u64 + cluster_size - 1) / cluster_size) as u32;
+        Ok(if allocated > needed {
+            AllocStatus::OverAllocated { slack: allocated - needed }
+        } else if allocated < needed {
+            AllocStatus::UnderAllocated { missing: needed - allocated }
+        } else {
+            AllocStatus::Exact
+        })
+    }
+
+    /// Checks that `size` is no larger than the whole data region, which no
+    /// file can exceed, or than `VfatConfig::max_file_size`, returning an
+    /// `InvalidData` error if it is. Unlike `validate_size`, no FAT entry is
+    /// read.
+    pub(crate) fn validate_size_bound(&self) -> io::Result<()> {
+        let vfat = self.vfat.try_borrow_mut()?;
+        let capacity = vfat.total_clusters() as u64 * vfat.cluster_size() as u64;
+        if self.size as u64 > capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File size exceeds the size of the volume.",
+            ));
+        }
+        check_max_file_size(&vfat, self.size as u64)
+    }
+
+    /// Checks that `size` does not claim more bytes than the file's cluster
+    /// chain holds, returning an `InvalidData` error if it does.
+    pub(crate) fn validate_size(&self) -> io::Result<()> {
+        let mut vfat = self.vfat.try_borrow_mut()?;
+        let allocated = vfat.chain_length(self.first_cluster)? as u64 * vfat.cluster_size() as u64;
+        if self.size as u64 > allocated {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File size exceeds its allocated clusters.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The checks run on a file resolved by a path walk before it is handed
+    /// out: `validate_size_bound` always, and `validate_size` as well if
+    /// `VfatConfig::strict_size` is set.
+    pub(crate) fn validate_for_open(&self) -> io::Result<()> {
+        self.validate_size_bound()?;
+        let strict_size = self.vfat.try_borrow_mut()?.config.strict_size;
+        if strict_size {
+            self.validate_size()?;
+        }
+        Ok(())
+    }
+}
+
+/// How a file's cluster chain compares with its size. Returned by
+/// `File::verify_allocation`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocStatus {
+    /// The chain has exactly as many clusters as the size calls for.
+    Exact,
+    /// The chain has `slack` clusters more than the size calls for.
+    OverAllocated { slack: u32 },
+    /// The size claims `missing` clusters more than the chain has.
+    UnderAllocated { missing: u32 },
+}
+
+/// Returns an `InvalidData` error if `size` exceeds
+/// `VfatConfig::max_file_size`.
+fn check_max_file_size(vfat: &VFat, size: u64) -> io::Result<()> {
+    match vfat.config.max_file_size {
+        Some(max) if size > max => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "File size exceeds the configured maximum file size.",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the `index`th cluster of the chain starting at `first`, or an
+/// `UnexpectedEof` error if the chain is shorter than that. The walk starts
+/// from `cursor` if it lies at or before `index`, taking one FAT lookup per
+/// cluster moved past, and `cursor` is moved to the cluster returned.
+fn nth_cluster(
+    vfat: &mut VFat,
+    first: Cluster,
+    cursor: &mut Option<(u32, Cluster)>,
+    index: u32,
+) -> io::Result<Cluster> {
+    let chain_ends = || {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Cluster chain ends before the file does.",
+        )
+    };
+    let (mut at, mut cluster) = match *cursor {
+        Some((at, cluster)) if at <= index => (at, cluster),
+        _ if first.inner() < 2 => return Err(chain_ends()),
+        _ => (0, first),
+    };
+    while at < index {
+        cluster = vfat.next_cluster(cluster)?.ok_or_else(&chain_ends)?;
+        at += 1;
+    }
+    *cursor = Some((index, cluster));
+    Ok(cluster)
+}
+
+/// An iterator over the sectors of a `File`. Returned by `File::sectors`.
+pub struct Sectors {
+    vfat: Shared<VFat>,
+    first_cluster: Cluster,
+    /// The rest of the file's cluster chain, read on the first call to `next`.
+    clusters: Option<vec::IntoIter<Cluster>>,
+    /// The cluster being read and the index of its next sector.
+    sector: Option<(Cluster, usize)>,
+    remaining: u64,
+}
+
+impl Sectors {
+    fn read_next(&mut self) -> io::Result<(u64, Vec<u8>)> {
+        let mut vfat = self.vfat.try_borrow_mut()?;
+        if self.clusters.is_none() {
+            let clusters = vfat.chain(self.first_cluster).collect::<io::Result<Vec<_>>>()?;
+            self.clusters = Some(clusters.into_iter());
+        }
+        let (cluster, index) = match self.sector {
+            Some((cluster, index)) if index < vfat.sectors_per_cluster() as usize => (cluster, index),
+            _ => match self.clusters.as_mut().and_then(|clusters| clusters.next()) {
+                Some(cluster) => (cluster, 0),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Cluster chain ends before the file does.",
+                    ))
+                }
+            },
+        };
+        self.sector = Some((cluster, index + 1));
+
+        let sector_size = vfat.sector_size();
+        let mut buf = vec![0; cmp::min(sector_size as u64, self.remaining) as usize];
+        let offset = index * sector_size;
+        let read = vfat.read_cluster_bounded(cluster, offset, offset + buf.len(), &mut buf)?;
+        buf.truncate(read);
+        self.remaining -= read as u64;
+        Ok((vfat.cluster_start_sector(cluster) + index as u64, buf))
+    }
+}
+
+impl Iterator for Sectors {
+    type Item = io::Result<(u64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let sector = self.read_next();
+        if sector.is_err() {
+            self.remaining = 0;
+        }
+        Some(sector)
     }
 }
 
@@ -78,47 +445,206 @@ impl io::Seek for File {
             ));
         }
         self.offset = offset as u32; // Works rely on the fact that maximum file size is 2**32 bits.
+        self.cursor = None;
         Ok(offset)
     }
 }
 
+#[cfg(feature = "write")]
+impl File {
+    /// Writes as much of `buf` as the maximum file size allows at the current
+    /// position, allocating clusters as the file grows, and records the new
+    /// size and first cluster in the file's directory entry.
+    fn write_through(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len() as u64, (u32::max_value() - self.offset) as u64) as usize;
+        if len == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "File has reached the maximum file size.",
+            ));
+        }
+        let (offset, end) = (self.offset, self.offset + len as u32);
+        self.reserve(end)?;
+        self.write_at(offset, &buf[..len])?;
+        self.offset = end;
+        Ok(len)
+    }
+
+    /// Extends the file to at least `end` bytes, allocating clusters as it
+    /// grows, and records the new size and first cluster in the file's
+    /// directory entry. The bytes it grows by are left as they were found.
+    fn reserve(&mut self, end: u32) -> io::Result<()> {
+        if end <= self.size {
+            return Ok(());
+        }
+        let entry_slot = self.entry_slot.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "File has no directory entry to record its size in.",
+            )
+        })?;
+        let mut vfat = self.vfat.try_borrow_mut()?;
+        let cluster_size = vfat.cluster_size() as u64;
+        let length = ((end as u64 + cluster_size - 1) / cluster_size) as usize;
+        let clusters = vfat.grow_chain(self.first_cluster, length)?;
+        let first_cluster = clusters.first().cloned().unwrap_or(self.first_cluster);
+        vfat.set_entry_cluster_and_size(entry_slot, first_cluster, end)?;
+        self.first_cluster = first_cluster;
+        self.size = end;
+        Ok(())
+    }
+
+    /// Writes `data` at `position`, within the file's current size.
+    fn write_at(&self, position: u32, data: &[u8]) -> io::Result<()> {
+        let mut vfat = self.vfat.try_borrow_mut()?;
+        let cluster_size = vfat.cluster_size();
+        let mut offset_in_cluster = position as usize % cluster_size;
+        let count = (offset_in_cluster + data.len() + cluster_size - 1) / cluster_size;
+        let clusters = vfat.chain(self.first_cluster)
+            .skip(position as usize / cluster_size)
+            .take(count)
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut written = 0;
+        for cluster in clusters {
+            written += vfat.write_cluster(cluster, offset_in_cluster, &data[written..])?;
+            offset_in_cluster = 0;
+        }
+        if written < data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Cluster chain ends before the file does.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the bytes held in the buffer to the volume.
+    fn flush_buffer(&self) -> io::Result<()> {
+        let mut buffer = self.buffer.borrow_mut();
+        if !buffer.data.is_empty() {
+            self.write_at(buffer.start, &buffer.data)?;
+            buffer.data.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "write")]
+impl Clone for File {
+    fn clone(&self) -> File {
+        let _ = self.flush_buffer();
+        File {
+            name: self.name.clone(),
+            short_name: self.short_name.clone(),
+            metadata: self.metadata.clone(),
+            size: self.size,
+            first_cluster: self.first_cluster,
+            vfat: self.vfat.clone(),
+            offset: self.offset,
+            cursor: self.cursor,
+            raw_name: self.raw_name.clone(),
+            entry_slot: self.entry_slot,
+            buffer: RefCell::new(WriteBuffer::default()),
+        }
+    }
+}
+
+#[cfg(feature = "write")]
+impl Drop for File {
+    /// Writes the bytes held in the buffer. Errors can't be reported from
+    /// here and are lost: call `flush` or `sync` before dropping a file that
+    /// was written to in order to see them.
+    fn drop(&mut self) {
+        let _ = self.flush_buffer();
+    }
+}
+
 impl io::Write for File {
+    /// Writes `buf` at the current position, extending the file if it runs
+    /// past the end. Data reaches the device on `sync`. With
+    /// `VfatConfig::write_buffer_size` set, small writes are held in memory
+    /// first.
+    #[cfg(feature = "write")]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let capacity = self.vfat.try_borrow_mut()?.config.write_buffer_size.unwrap_or(0);
+        let (held, continues) = {
+            let buffer = self.buffer.borrow();
+            let held = buffer.data.len();
+            (held, buffer.start as u64 + held as u64 == self.offset as u64)
+        };
+        if held > 0 && (!continues || held + buf.len() > capacity) {
+            self.flush_buffer()?;
+        }
+        let end = self.offset as u64 + buf.len() as u64;
+        if self.buffer.borrow().data.len() + buf.len() > capacity || end > u32::max_value() as u64 {
+            return self.write_through(buf);
+        }
+        self.reserve(end as u32)?;
+        let mut buffer = self.buffer.borrow_mut();
+        if buffer.data.is_empty() {
+            buffer.start = self.offset;
+        }
+        buffer.data.extend_from_slice(buf);
+        self.offset = end as u32;
+        Ok(buf.len())
+    }
+
+    #[cfg(not(feature = "write"))]
     fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("Read-only!")
+        Err(read_only_error())
+    }
+
+    /// Writes the bytes held in the buffer to the volume; `sync` writes
+    /// changes to the device.
+    #[cfg(feature = "write")]
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buffer()
     }
 
+    #[cfg(not(feature = "write"))]
     fn flush(&mut self) -> io::Result<()> {
-        unimplemented!("Read-only")
+        Err(read_only_error())
     }
 }
 
 impl io::Read for File {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "write")]
+        self.flush_buffer()?;
         // io::Read does not need all octets are returned at once
-        if self.size == 0 {
+        if self.offset >= self.size {
             return Ok(0);
         }
         let read_bytes = {
-            let mut vfat = self.vfat.borrow_mut();
-            let cluster = self.first_cluster.inner() + self.offset / vfat.cluster_size() as u32;
+            let mut vfat = self.vfat.try_borrow_mut()?;
+            let index = self.offset / vfat.cluster_size() as u32;
+            let cluster = nth_cluster(&mut vfat, self.first_cluster, &mut self.cursor, index)?;
             let offset_in_cluster = self.offset as usize % vfat.cluster_size();
             let available_bytes = (self.size - self.offset) as usize;
-            let len = min(available_bytes, buf.len());
-            vfat.read_cluster(
-                cluster.into(),
+            vfat.read_cluster_bounded(
+                cluster,
                 offset_in_cluster,
-                &mut buf[..len],
+                offset_in_cluster + available_bytes,
+                buf,
             )?
         };
-        self.seek(SeekFrom::Current(read_bytes as i64))?;
+        self.offset += read_bytes as u32;
         Ok(read_bytes)
     }
 }
 
 impl traits::File for File {
     /// Writes any buffered data to disk.
+    #[cfg(feature = "write")]
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush_buffer()?;
+        self.vfat.try_borrow_mut()?.sync()
+    }
+
+    /// Writes any buffered data to disk.
+    #[cfg(not(feature = "write"))]
     fn sync(&mut self) -> io::Result<()> {
-        unimplemented!("Read-only!");
+        Err(read_only_error())
     }
 
     /// Returns the size of the file in bytes.
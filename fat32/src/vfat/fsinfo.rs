@@ -0,0 +1,35 @@
+use std::fmt;
+
+/// The FSInfo sector, which caches the number of free clusters and a hint for
+/// where to start looking for one. Both values are advisory and may be
+/// `0xFFFFFFFF` ("unknown").
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: u32,
+    __r0: [u8; 480],
+    struct_signature: u32,
+    /// Last known number of free clusters.
+    pub free_count: u32,
+    /// Cluster number at which to start looking for free clusters.
+    pub next_free: u32,
+    __r1: [u8; 12],
+    trail_signature: u32,
+}
+
+impl FsInfo {
+    /// Whether the three FSInfo signatures are intact.
+    pub fn is_valid(&self) -> bool {
+        self.lead_signature == 0x41615252 && self.struct_signature == 0x61417272 &&
+            self.trail_signature == 0xAA550000
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("free_count", &self.free_count)
+            .field("next_free", &self.next_free)
+            .field("valid", &self.is_valid())
+            .finish()
+    }
+}
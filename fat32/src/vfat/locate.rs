@@ -0,0 +1,82 @@
+use std::io;
+use std::path::Path;
+
+use traits::FileSystem;
+use vfat::{Attributes, Cluster, DirEntryParser, Shared, VFat};
+
+/// Where the 8.3 directory slot describing an entry is stored. Returned by
+/// `Shared<VFat>::locate_entry`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EntryLocation {
+    /// The cluster holding the slot.
+    pub cluster: Cluster,
+    /// The byte offset of the slot within `cluster`.
+    pub offset: usize,
+}
+
+impl Shared<VFat> {
+    /// Returns the location of the 8.3 directory slot describing the entry at
+    /// `path`, i.e. the slot recording its attributes, timestamps, first
+    /// cluster and size. Any LFN slots belonging to the entry precede it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` is the root directory or
+    /// ends in `..`, neither of which has a slot of its own. Returns an error
+    /// of `NotFound` if there is no entry at `path`, or an error if its
+    /// parent directory can't be opened or read.
+    pub fn locate_entry<P: AsRef<Path>>(&self, path: P) -> io::Result<EntryLocation> {
+        let path = path.as_ref();
+        let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Path has no directory entry of its own.",
+                ))
+            }
+        };
+        let name = name.to_str().ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "File name contains non unicode charaters.",
+        ))?;
+        let first_cluster = self.open_dir(parent)?.first_cluster();
+
+        let mut vfat = self.try_borrow_mut()?;
+        if first_cluster.inner() >= 2 {
+            let raw = vfat.read_dir_raw(first_cluster)?;
+            let codec = vfat.config.oem_codec.clone();
+            // The slots of the entry being assembled: its LFN slots, if any,
+            // and then its 8.3 slot.
+            let mut run_start = 0;
+            for (index, slot) in raw.chunks(32).enumerate() {
+                match slot[0] {
+                    0x00 => break,
+                    0xE5 => {
+                        run_start = index + 1;
+                        continue;
+                    }
+                    _ if Attributes::from(slot[11]).lfn() => continue,
+                    _ => (),
+                }
+                let run = &raw[run_start * 32..(index + 1) * 32];
+                run_start = index + 1;
+                let found = DirEntryParser::from_bytes_with_codec(run, codec.clone())
+                    .last()
+                    .map_or(false, |entry| entry.name.eq_ignore_ascii_case(name));
+                if found {
+                    let cluster_size = vfat.cluster_size();
+                    let cluster = vfat.chain(first_cluster).nth(index * 32 / cluster_size);
+                    return Ok(EntryLocation {
+                        cluster: cluster.expect("slot lies within the chain just read")?,
+                        offset: index * 32 % cluster_size,
+                    });
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "File is not found.",
+        ))
+    }
+}
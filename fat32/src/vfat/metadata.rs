@@ -1,4 +1,5 @@
 use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use traits;
 
@@ -112,14 +113,68 @@ impl traits::Timestamp for Timestamp {
     }
 }
 
+impl Timestamp {
+    /// Converts the timestamp into a `SystemTime`, interpreting it as UTC.
+    /// FAT records local time without its offset from UTC; if the volume's
+    /// time zone is known, use `to_system_time_with_offset` instead.
+    ///
+    /// Returns `None` if the on-disk date is not a valid calendar date (as is
+    /// the case for the root directory, which has no timestamps).
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        self.to_system_time_with_offset(0)
+    }
+
+    /// Converts the timestamp into a `SystemTime`, interpreting it as local
+    /// time `utc_offset_minutes` minutes ahead of UTC (e.g. 540 for JST,
+    /// -300 for EST).
+    ///
+    /// Returns `None` if the on-disk date is not a valid calendar date.
+    pub fn to_system_time_with_offset(&self, utc_offset_minutes: i32) -> Option<SystemTime> {
+        use traits::Timestamp;
+        let (year, month, day) = (self.year() as u64, self.month() as u64, self.day() as u64);
+        if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        // Days since 1970-01-01, after Howard Hinnant's `days_from_civil`.
+        // FAT years start at 1980, so nothing here can go negative.
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+        let secs = days * 86400 + self.hour() as u64 * 3600 + self.minute() as u64 * 60 +
+            self.second() as u64;
+        let local = UNIX_EPOCH + Duration::from_secs(secs);
+        let offset = Duration::from_secs((utc_offset_minutes as i64).abs() as u64 * 60);
+        Some(if utc_offset_minutes >= 0 { local - offset } else { local + offset })
+    }
+}
+
+/// The number of days in `month` (1 for January) of `year`, in the Gregorian
+/// calendar.
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
 impl Attributes {
-    const READ_ONLY: u8 = 0x01;
-    const HIDDEN: u8 = 0x02;
-    const SYSTEM: u8 = 0x04;
-    const VOLUME_ID: u8 = 0x08;
-    const DIRECTORY: u8 = 0x10;
-    const ARCHIVE: u8 = 0x20;
-    const LFN: u8 = Self::READ_ONLY | Self::HIDDEN | Self::SYSTEM | Self::VOLUME_ID;
+    pub const READ_ONLY: u8 = 0x01;
+    pub const HIDDEN: u8 = 0x02;
+    pub const SYSTEM: u8 = 0x04;
+    pub const VOLUME_ID: u8 = 0x08;
+    pub const DIRECTORY: u8 = 0x10;
+    pub const ARCHIVE: u8 = 0x20;
+    pub const LFN: u8 = Self::READ_ONLY | Self::HIDDEN | Self::SYSTEM | Self::VOLUME_ID;
+
+    /// The raw attribute byte, as stored in the directory entry.
+    pub fn raw(&self) -> u8 {
+        self.0
+    }
 
     // `val & mask == mask` is necessary!
     // barely `!= 0` does not work because there is mask like 0x10 which has two or more bits set
@@ -165,6 +220,26 @@ impl traits::Metadata for Metadata {
         self.attributes.hidden()
     }
 
+    /// Whether the entry belongs to the operating system.
+    fn system(&self) -> bool {
+        self.attributes.system()
+    }
+
+    /// Whether the entry has changed since it was last archived.
+    fn archive(&self) -> bool {
+        self.attributes.archive()
+    }
+
+    /// Whether the entry holds the volume label rather than a file.
+    fn volume_id(&self) -> bool {
+        self.attributes.volume_id()
+    }
+
+    /// Whether the entry is a directory.
+    fn is_dir(&self) -> bool {
+        self.attributes.directory()
+    }
+
     /// The timestamp when the entry was created.
     fn created(&self) -> Self::Timestamp {
         self.created_time
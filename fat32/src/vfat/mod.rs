@@ -9,17 +9,38 @@ pub(crate) mod entry;
 pub(crate) mod metadata;
 pub(crate) mod cache;
 pub(crate) mod shared;
+pub(crate) mod config;
+pub(crate) mod walk;
+pub(crate) mod oem;
+pub(crate) mod probe;
+pub(crate) mod locate;
+#[cfg(not(target_os = "ros"))]
+pub(crate) mod extract;
+#[cfg(feature = "write")]
+pub(crate) mod fsinfo;
+#[cfg(feature = "write")]
+pub(crate) mod create;
+#[cfg(feature = "write")]
+pub(crate) mod copy;
 
-pub use self::ebpb::BiosParameterBlock;
-pub use self::file::File;
-pub use self::dir::Dir;
-pub use self::error::Error;
-pub use self::vfat::VFat;
+pub use self::ebpb::{BiosParameterBlock, FatType};
+pub use self::file::{AllocStatus, File, Sectors};
+pub use self::dir::{Dir, DirEntryParser, EntryIter, ParsedEntry, RawDirEntry, StreamingEntryIter};
+pub use self::dir::{sfn_checksum, validate_filename};
+pub use self::error::{is_read_only_error, read_only_error, Error};
+pub use self::vfat::{ReservedLayout, VFat};
 pub use self::entry::Entry;
 pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
 use self::metadata::ROOTMETADATA;
 pub use self::shared::Shared;
+pub use self::config::VfatConfig;
+pub use self::walk::{Walk, WalkEntry};
+pub use self::oem::OemCodec;
+pub use self::probe::{probe, FatProbe, FsProbe, PartitionProbe, PartitionTable};
+pub use self::locate::EntryLocation;
 
 pub(crate) use self::cache::{CachedDevice, Partition};
 pub(crate) use self::fat::{Status, FatEntry};
-pub(crate) use self::cluster::Cluster;
+#[cfg(feature = "write")]
+pub(crate) use self::fsinfo::FsInfo;
+pub use self::cluster::Cluster;
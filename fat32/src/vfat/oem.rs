@@ -0,0 +1,54 @@
+/// Code page 437 characters for bytes 0x80 to 0xFF.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+                          ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+                          αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}";
+
+/// Maps the bytes of 8.3 short names, which are stored in the OEM code page
+/// of the system that wrote them, to characters. Bytes below 0x80 are ASCII;
+/// the rest are looked up in a table. Only single-byte code pages can be
+/// described.
+///
+/// Long file names are always UTF-16 and never pass through an `OemCodec`.
+#[derive(Debug, Clone)]
+pub struct OemCodec {
+    /// The characters for bytes 0x80 to 0xFF.
+    high: Vec<char>,
+}
+
+impl OemCodec {
+    /// Code page 437, the original IBM PC character set. This is the default.
+    pub fn cp437() -> OemCodec {
+        OemCodec {
+            high: CP437_HIGH.chars().collect(),
+        }
+    }
+
+    /// A code page whose bytes 0x80 to 0xFF map to `high[0]` to `high[127]`.
+    pub fn from_table(high: [char; 128]) -> OemCodec {
+        OemCodec { high: high.to_vec() }
+    }
+
+    /// Returns the character for `byte`.
+    pub fn decode(&self, byte: u8) -> char {
+        if byte < 0x80 {
+            byte as char
+        } else {
+            self.high[byte as usize - 0x80]
+        }
+    }
+
+    /// Returns the byte for `c`, or `None` if the code page lacks it.
+    pub fn encode(&self, c: char) -> Option<u8> {
+        if (c as u32) < 0x80 {
+            Some(c as u8)
+        } else {
+            self.high.iter().position(|&high| high == c).map(|i| 0x80 + i as u8)
+        }
+    }
+}
+
+impl Default for OemCodec {
+    fn default() -> OemCodec {
+        OemCodec::cp437()
+    }
+}
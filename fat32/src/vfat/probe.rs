@@ -0,0 +1,138 @@
+use std::io;
+
+use mbr::{self, MasterBootRecord};
+use traits::BlockDevice;
+use vfat::{BiosParameterBlock, Error, FatType};
+
+/// The partition types of FAT12, FAT16 and FAT32 file systems.
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
+
+/// The kind of partition table found by `probe`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartitionTable {
+    /// No partition table: the device is a bare file system or unrecognized.
+    None,
+    Mbr,
+    /// A GUID partition table behind a protective MBR. Its entries are not
+    /// read.
+    Gpt,
+}
+
+/// What `probe` learned about a FAT file system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FatProbe {
+    pub fat_type: FatType,
+    /// The label recorded in the boot sector, if any.
+    pub volume_label: Option<String>,
+}
+
+/// A non-empty entry of the MBR partition table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionProbe {
+    /// The position of the entry in the partition table (0-indexed).
+    pub index: u8,
+    pub partition_type: u8,
+    /// The first sector of the partition.
+    pub start: u64,
+    pub sectors: u64,
+    /// The file system, if the partition type is a FAT one and the partition
+    /// holds a recognizable boot sector.
+    pub fat: Option<FatProbe>,
+}
+
+/// An inventory of a device, as returned by `probe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsProbe {
+    pub table: PartitionTable,
+    /// The non-empty entries of the MBR partition table, in table order.
+    pub partitions: Vec<PartitionProbe>,
+    /// The file system occupying the whole device, if it has no partition
+    /// table.
+    pub volume: Option<FatProbe>,
+}
+
+/// Inspects `device` without mounting it: reads the partition table and, for
+/// each FAT partition (or the whole device, if it is unpartitioned), the boot
+/// sector. No FAT or directory is read.
+///
+/// A device whose sector 0 is neither a valid MBR nor a FAT boot sector is
+/// reported with `PartitionTable::None` and nothing else.
+///
+/// # Errors
+///
+/// Returns an error if reading from `device` fails, other than by running
+/// off its end.
+pub fn probe<T: BlockDevice>(mut device: T) -> io::Result<FsProbe> {
+    let mut probe = FsProbe {
+        table: PartitionTable::None,
+        partitions: Vec::new(),
+        volume: None,
+    };
+
+    // A bare file system's boot sector also carries the 0x55AA signature, so
+    // check for one before trusting the partition table.
+    if let Some(fat) = probe_fat(&mut device, 0)? {
+        probe.volume = Some(fat);
+        return Ok(probe);
+    }
+    let mbr = match MasterBootRecord::from(&mut device) {
+        Ok(mbr) => mbr,
+        Err(mbr::Error::Io(e)) => return Err(e),
+        Err(_) => return Ok(probe),
+    };
+
+    probe.table = PartitionTable::Mbr;
+    for (index, entry) in mbr.partition_table.iter().enumerate() {
+        let partition_type = entry.partition_type;
+        if partition_type == 0 {
+            continue;
+        }
+        if partition_type == 0xEE && is_gpt(&mut device)? {
+            probe.table = PartitionTable::Gpt;
+        }
+        let start = entry.relative_sector as u64;
+        let fat = if FAT_PARTITION_TYPES.contains(&partition_type) {
+            probe_fat(&mut device, start)?
+        } else {
+            None
+        };
+        probe.partitions.push(PartitionProbe {
+            index: index as u8,
+            partition_type,
+            start,
+            sectors: entry.total_sectors as u64,
+            fat,
+        });
+    }
+    Ok(probe)
+}
+
+/// Reads the boot sector at `sector`, returning `None` if it is not a FAT
+/// one.
+fn probe_fat<T: BlockDevice>(device: &mut T, sector: u64) -> io::Result<Option<FatProbe>> {
+    let bpb = match BiosParameterBlock::read(device, sector) {
+        Ok(bpb) => bpb,
+        // A partition entry may point past the end of the device.
+        Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(Error::Io(e)) => return Err(e),
+        Err(_) => return Ok(None),
+    };
+    let bps = bpb.bytes_per_sector;
+    let plausible = bps >= 512 && bps <= 4096 && bps.is_power_of_two() &&
+        bpb.sectors_per_cluster.is_power_of_two() && bpb.number_of_fats > 0 &&
+        bpb.number_of_reserved_sectors > 0;
+    if !plausible {
+        return Ok(None);
+    }
+    Ok(bpb.fat_type().map(|fat_type| FatProbe {
+        fat_type,
+        volume_label: bpb.volume_label(),
+    }))
+}
+
+/// Checks for the GPT header signature in sector 1.
+fn is_gpt<T: BlockDevice>(device: &mut T) -> io::Result<bool> {
+    let mut buf = [0u8; 512];
+    device.read_sector_exact(1, &mut buf)?;
+    Ok(&buf[..8] == b"EFI PART")
+}
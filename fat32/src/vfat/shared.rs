@@ -1,4 +1,6 @@
+use std::io;
 use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, TryLockError};
 
 /// A smart pointer to a shared instance of type `T`.
 ///
@@ -6,20 +8,32 @@ use std::ops::{Deref, DerefMut};
 /// `.borrow_mut()`. The implementation guarantees the usual reference
 /// guarantees.
 #[derive(Debug)]
-pub struct Shared<T>(imp::Inner<T>);
+pub struct Shared<T>(imp::Inner<Lock<T>>);
+
+/// The value behind a `Shared`, along with the thread currently borrowing it,
+/// which lets `try_borrow_mut` tell a reentrant borrow from a contended one.
+#[derive(Debug)]
+struct Lock<T> {
+    value: Mutex<T>,
+    owner: Mutex<Option<imp::Owner>>,
+}
 
 #[cfg(target_os = "ros")]
 mod imp {
     use std::rc::Rc;
-    use std::sync::Mutex;
     use super::Shared;
 
-    pub type Inner<T> = Rc<Mutex<T>>;
+    pub type Inner<T> = Rc<T>;
+
+    /// There is a single thread, so every borrow is by the current one.
+    pub type Owner = ();
 
     pub fn new<T>(val: T) -> Inner<T> {
-        Rc::new(Mutex::new(val))
+        Rc::new(val)
     }
 
+    pub fn current() -> Owner {}
+
     // Without an enabled MMU/cache, the processor faults on atomic accesses.
     // As such, use an `Rc` instead of an `Arc` when running on ROS until
     // multithreading, the MMU, and caches are enabled.
@@ -29,19 +43,64 @@ mod imp {
 
 #[cfg(not(target_os = "ros"))]
 mod imp {
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
+    use std::thread::{self, ThreadId};
+
+    pub type Inner<T> = Arc<T>;
 
-    pub type Inner<T> = ::std::sync::Arc<::std::sync::Mutex<T>>;
+    pub type Owner = ThreadId;
 
     pub fn new<T>(val: T) -> Inner<T> {
-        Arc::new(Mutex::new(val))
+        Arc::new(val)
+    }
+
+    pub fn current() -> Owner {
+        thread::current().id()
+    }
+}
+
+/// A borrow of the value behind a `Shared`. Dropping it releases the borrow.
+struct Guard<'a, T: 'a> {
+    value: MutexGuard<'a, T>,
+    owner: &'a Mutex<Option<imp::Owner>>,
+}
+
+impl<'a, T> Guard<'a, T> {
+    fn new(value: MutexGuard<'a, T>, owner: &'a Mutex<Option<imp::Owner>>) -> Guard<'a, T> {
+        *owner.lock().unwrap_or_else(|e| e.into_inner()) = Some(imp::current());
+        Guard { value, owner }
+    }
+}
+
+impl<'a, T> Drop for Guard<'a, T> {
+    fn drop(&mut self) {
+        // Runs before `value` is dropped, so the owner is cleared while the
+        // borrow is still held.
+        *self.owner.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+impl<'a, T> Deref for Guard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<'a, T> DerefMut for Guard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
     }
 }
 
 impl<T> Shared<T> {
     /// Wraps `val` into a `Shared<T>` and returns it.
     pub fn new(val: T) -> Shared<T> {
-        Shared(imp::new(val))
+        Shared(imp::new(Lock {
+            value: Mutex::new(val),
+            owner: Mutex::new(None),
+        }))
     }
 
     /// Returns an immutable borrow to the inner value.
@@ -49,7 +108,7 @@ impl<T> Shared<T> {
     /// If the inner value is presently mutably borrowed, this function blocks
     /// until that borrow is returned.
     pub fn borrow<'a>(&'a self) -> impl Deref<Target = T> + 'a {
-        self.0.lock().expect("all okay")
+        Guard::new(self.0.value.lock().expect("all okay"), &self.0.owner)
     }
 
     /// Returns an mutable borrow to the inner value.
@@ -57,8 +116,43 @@ impl<T> Shared<T> {
     /// If the inner value is presently borrowed, mutably or immutably, this
     /// function blocks until all borrows are returned.
     pub fn borrow_mut<'a>(&'a self) -> impl DerefMut<Target = T> + 'a {
-        self.0.lock().expect("all okay")
+        Guard::new(self.0.value.lock().expect("all okay"), &self.0.owner)
     }
+
+    /// Returns a mutable borrow to the inner value. Like `borrow_mut`, this
+    /// blocks while another thread borrows the value, but it fails instead of
+    /// deadlocking if the calling thread already does.
+    ///
+    /// # Errors
+    ///
+    /// If the inner value is presently borrowed by the calling thread, returns
+    /// an error of `WouldBlock`. If a thread panicked while borrowing it,
+    /// returns an error of `Other`.
+    pub fn try_borrow_mut<'a>(&'a self) -> io::Result<impl DerefMut<Target = T> + 'a> {
+        let value = match self.0.value.try_lock() {
+            Ok(value) => value,
+            Err(TryLockError::WouldBlock) => {
+                let owner = *self.0.owner.lock().unwrap_or_else(|e| e.into_inner());
+                if owner == Some(imp::current()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WouldBlock,
+                        "Value is already borrowed.",
+                    ));
+                }
+                self.0.value.lock().map_err(|_| poisoned())?
+            }
+            Err(TryLockError::Poisoned(_)) => return Err(poisoned()),
+        };
+        Ok(Guard::new(value, &self.0.owner))
+    }
+}
+
+/// The error returned when a thread panicked while borrowing the value.
+fn poisoned() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "Value was left poisoned by a panic while borrowed.",
+    )
 }
 
 impl<T> Clone for Shared<T> {
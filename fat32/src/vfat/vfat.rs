@@ -1,11 +1,38 @@
 use std::cmp::min;
+use std::collections::HashSet;
 use std::io;
-use std::path::{Component, Path};
+use std::path::{Component, Path, PathBuf};
 
-use mbr::MasterBootRecord;
+use mbr::{MasterBootRecord, UnsupportedFs};
 use traits::{BlockDevice, FileSystem};
-use vfat::{BiosParameterBlock, CachedDevice, Partition};
-use vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Shared, Status};
+use vfat::{BiosParameterBlock, CachedDevice, FatType, Partition};
+use vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Shared, Status, VfatConfig};
+use vfat::DirEntryParser;
+use vfat::walk::too_deep;
+#[cfg(not(feature = "write"))]
+use vfat::error::read_only_error;
+#[cfg(feature = "write")]
+use vfat::{validate_filename, FsInfo};
+#[cfg(feature = "write")]
+use vfat::create;
+#[cfg(feature = "write")]
+use vfat::dir::EntrySlot;
+#[cfg(feature = "write")]
+use vfat::sfn_checksum;
+
+/// Where the structures of the reserved region lie, in sectors relative to
+/// the start of the partition. Returned by `VFat::reserved_region`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReservedLayout {
+    /// The boot sector holding the EBPB. Always 0.
+    pub boot_sector: u64,
+    /// The FSInfo sector, if the volume has one.
+    pub fsinfo_sector: Option<u64>,
+    /// The backup copy of the boot sector, if the volume has one.
+    pub backup_boot_sector: Option<u64>,
+    /// The number of sectors in the reserved region, boot sector included.
+    pub reserved_sectors: u64,
+}
 
 #[derive(Debug)]
 pub struct VFat {
@@ -15,39 +42,203 @@ pub struct VFat {
     sectors_per_fat: u32,
     fat_start_sector: u64,
     data_start_sector: u64,
+    data_sectors: u64,
+    total_clusters: u32,
+    total_sectors: u64,
+    reserved: ReservedLayout,
+    #[cfg(feature = "write")]
+    number_of_fats: u8,
+    #[cfg(feature = "write")]
+    fsinfo_sector: Option<u64>,
+    #[cfg(feature = "write")]
+    boot_sectors: Vec<u64>,
+    /// Whether this mount has cleared the clean shutdown bit on the device,
+    /// which happens just before its first modification.
+    #[cfg(feature = "write")]
+    marked_dirty: bool,
     pub(super) root_dir_cluster: Cluster,
+    pub(super) config: VfatConfig,
 }
 
 impl VFat {
-    pub fn from<T>(mut device: T) -> Result<Shared<VFat>, Error>
+    pub fn from<T>(device: T) -> Result<Shared<VFat>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        VFat::from_with_config(device, VfatConfig::default())
+    }
+
+    /// Like `from`, but mounts the file system with the options in `config`.
+    ///
+    /// # Errors
+    ///
+    /// If the device has no usable partition table but sector 0 holds a FAT32
+    /// EBPB, returns `Error::Unpartitioned`: such a device should be mounted
+    /// with `VFat::from_unpartitioned_with_config`. If the partition table has no FAT32
+    /// partition, returns `Error::UnsupportedFs` naming the first partition,
+    /// or `Error::NotFound` if the table is empty.
+    pub fn from_with_config<T>(mut device: T, config: VfatConfig) -> Result<Shared<VFat>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let mbr = if config.lenient_boot_indicators {
+            MasterBootRecord::from_lenient(&mut device)
+        } else {
+            MasterBootRecord::from(&mut device)
+        };
+        let start = match mbr {
+            Ok(mbr) => mbr.identify_first_supported().map(|p| p.relative_sector as u64),
+            Err(e) => {
+                if is_unpartitioned(&mut device) {
+                    return Err(Error::Unpartitioned);
+                }
+                return Err(e.into());
+            }
+        };
+        match start {
+            Ok(start) => VFat::from_partition(device, start, config),
+            Err(_) if is_unpartitioned(&mut device) => Err(Error::Unpartitioned),
+            Err(UnsupportedFs::NoPartitions) => Err(Error::NotFound),
+            Err(unsupported) => Err(Error::UnsupportedFs(unsupported)),
+        }
+    }
+
+    /// Mounts a device holding a bare FAT32 file system, i.e. one without a
+    /// partition table (as made by running `mkfs.fat` on a whole device). The
+    /// EBPB is read from sector 0 and the whole device is the partition.
+    pub fn from_unpartitioned<T>(device: T) -> Result<Shared<VFat>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        VFat::from_unpartitioned_with_config(device, VfatConfig::default())
+    }
+
+    /// Like `from_unpartitioned`, but mounts the file system with the options
+    /// in `config`.
+    pub fn from_unpartitioned_with_config<T>(
+        device: T,
+        config: VfatConfig,
+    ) -> Result<Shared<VFat>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        VFat::from_partition(device, 0, config)
+    }
+
+    /// Mounts the FAT32 partition whose EBPB is at physical sector `start`.
+    fn from_partition<T>(
+        mut device: T,
+        start: u64,
+        config: VfatConfig,
+    ) -> Result<Shared<VFat>, Error>
     where
         T: BlockDevice + 'static,
     {
-        let mbr = MasterBootRecord::from(&mut device)?;
-        let fat32 = mbr.first_fat32_partition().ok_or(Error::NotFound)?;
-        let bpb = BiosParameterBlock::from(&mut device, fat32.relative_sector as u64)?;
+        let bpb = BiosParameterBlock::from(&mut device, start)?;
+        let device_sector_size = device.sector_size();
+        if (bpb.bytes_per_sector as u64) < device_sector_size ||
+            bpb.bytes_per_sector as u64 % device_sector_size != 0
+        {
+            return Err(Error::InvalidBpb(
+                "Logical sector size is not a multiple of the device's sector size.",
+            ));
+        }
+        // FAT12/16 keep the root directory in a fixed region and the FAT size
+        // in a 16-bit field; FAT32 leaves both zero.
+        if bpb.max_no_of_director_entries != 0 {
+            return Err(Error::InvalidBpb(
+                "Root directory entry count is nonzero, as for FAT12/16.",
+            ));
+        }
+        if bpb._number_of_sectors_per_fat != 0 {
+            return Err(Error::InvalidBpb(
+                "16-bit sectors per FAT is nonzero, as for FAT12/16.",
+            ));
+        }
+        // Catches truncated images and garbled FAT sizes before any FAT entry
+        // is read from beyond the volume.
+        let fat_region_end = bpb.number_of_reserved_sectors as u64 +
+            bpb.number_of_fats as u64 * bpb.sectors_per_fat as u64;
+        if fat_region_end > bpb.total_sectors() as u64 {
+            return Err(Error::InvalidBpb(
+                "Reserved sectors and FATs extend past the end of the volume.",
+            ));
+        }
+        // The FAT type is decided by the cluster count alone, so a volume
+        // with FAT32 fields but too few clusters would be read as FAT12/16 by
+        // any other implementation.
+        match bpb.fat_type() {
+            Some(FatType::Fat32) => {}
+            Some(FatType::Fat16) => {
+                return Err(Error::InvalidBpb("Not a FAT32 volume: detected FAT16."))
+            }
+            Some(FatType::Fat12) => {
+                return Err(Error::InvalidBpb("Not a FAT32 volume: detected FAT12."))
+            }
+            None => {
+                return Err(Error::InvalidBpb("Not a FAT32 volume: FAT type is undeterminable."))
+            }
+        }
 
         let bps = bpb.bytes_per_sector;
         let spc = bpb.sectors_per_cluster;
         let spf = bpb.sectors_per_fat;
-        let fss = fat32.relative_sector as u64 /* start of partition */ /*+ 1  BPB */ + bpb.number_of_reserved_sectors as u64;
+        let fss = start /* start of partition */ /*+ 1  BPB */ + bpb.number_of_reserved_sectors as u64;
         let rdc: Cluster = bpb.cluster_no_of_root_directory.into(); // TODO: NOTIMPLEMTNED YET!
-        let cached_device = CachedDevice::new(
+        let data_sectors = (bpb.total_sectors() as u64).saturating_sub(
+            bpb.number_of_reserved_sectors as u64 + bpb.number_of_fats as u64 * spf as u64,
+        );
+        let total_clusters = data_sectors.checked_div(spc as u64).unwrap_or(0) as u32;
+        let mut cached_device = CachedDevice::new(
             device,
             Partition {
-                start: fat32.relative_sector as u64,
+                start,
                 sector_size: bpb.bytes_per_sector as u64,
             },
         );
-        let vfat = VFat {
+        cached_device.set_capacity(config.cache_capacity);
+        let mut vfat = VFat {
             device: cached_device,
             bytes_per_sector: bps,
             sectors_per_cluster: spc,
             sectors_per_fat: spf,
             fat_start_sector: fss,
             data_start_sector: fss as u64 + bpb.number_of_fats as u64 * bpb.sectors_per_fat as u64,
+            data_sectors,
+            total_clusters,
+            total_sectors: bpb.total_sectors() as u64,
+            reserved: ReservedLayout {
+                boot_sector: 0,
+                fsinfo_sector: match bpb.fsinfo_sector() {
+                    0 | 0xFFFF => None,
+                    n => Some(n as u64),
+                },
+                backup_boot_sector: match bpb.backup_boot_sector() {
+                    0 | 0xFFFF => None,
+                    n => Some(n as u64),
+                },
+                reserved_sectors: bpb.number_of_reserved_sectors as u64,
+            },
+            #[cfg(feature = "write")]
+            number_of_fats: bpb.number_of_fats,
+            #[cfg(feature = "write")]
+            fsinfo_sector: match bpb.fsinfo_sector() {
+                0 | 0xFFFF => None,
+                n => Some(start + n as u64),
+            },
+            #[cfg(feature = "write")]
+            boot_sectors: match bpb.backup_boot_sector() {
+                0 | 0xFFFF => vec![start],
+                n => vec![start, start + n as u64],
+            },
+            #[cfg(feature = "write")]
+            marked_dirty: false,
             root_dir_cluster: rdc,
+            config,
         };
+        if vfat.config.validate_root {
+            vfat.validate_root()?;
+        }
         Ok(Shared::new(vfat))
     }
 
@@ -56,6 +247,46 @@ impl VFat {
         self.sectors_per_cluster as usize * self.bytes_per_sector as usize
     }
 
+    /// The size of a logical sector in bytes, as recorded in the BPB.
+    pub fn sector_size(&self) -> usize {
+        self.bytes_per_sector as usize
+    }
+
+    pub fn sectors_per_cluster(&self) -> u8 {
+        self.sectors_per_cluster
+    }
+
+    /// The number of sectors occupied by each copy of the FAT.
+    pub fn sectors_per_fat(&self) -> u32 {
+        self.sectors_per_fat
+    }
+
+    /// The number of data clusters on the volume. Valid cluster numbers are
+    /// `2..total_clusters() + 2`.
+    pub fn total_clusters(&self) -> u32 {
+        self.total_clusters
+    }
+
+    /// Returns the first sector of `cluster`, numbered the same way as the
+    /// sectors of the underlying device (i.e. including the partition offset).
+    ///
+    /// `cluster` must be a data cluster, i.e. numbered 2 or above.
+    pub fn cluster_start_sector(&self, cluster: Cluster) -> u64 {
+        self.data_start_sector + (cluster.inner() as u64 - 2) * self.sectors_per_cluster as u64
+    }
+
+    /// Returns the first sector of the data region, numbered like the sectors
+    /// of `cluster_start_sector`, and the number of sectors in it.
+    pub fn data_region(&self) -> (u64, u64) {
+        (self.data_start_sector, self.data_sectors)
+    }
+
+    /// Returns the layout of the reserved region at the start of the
+    /// partition.
+    pub fn reserved_region(&self) -> ReservedLayout {
+        self.reserved
+    }
+
     // TODO: The following methods may be useful here:
     //
     ///  * A method to read from an offset of a cluster into a buffer.
@@ -72,13 +303,13 @@ impl VFat {
                 "Cluster is bad.",
             ));
         }
-        let mut nsector = self.data_start_sector +
-            (cluster.inner() as u64).checked_sub(2).ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Cluster number should be greater or equal than 2.",
-                )
-            })? * self.sectors_per_cluster as u64 +
+        if cluster.inner() < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cluster number should be greater or equal than 2.",
+            ));
+        }
+        let mut nsector = self.cluster_start_sector(cluster) +
             offset as u64 / self.bytes_per_sector as u64;
         let mut index = {
             let sector = self.device.get(nsector)?;
@@ -100,13 +331,114 @@ impl VFat {
         Ok(total)
     }
 
+    /// Like `read_cluster`, but never reads at or beyond byte `limit` of the
+    /// cluster, e.g. the end of a file whose last cluster is only partly
+    /// used. Returns `Ok(0)` if `offset` is at or beyond `limit`.
+    pub fn read_cluster_bounded(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        limit: usize,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        let len = min(buf.len(), limit.saturating_sub(offset));
+        if len == 0 {
+            return Ok(0);
+        }
+        self.read_cluster(cluster, offset, &mut buf[..len])
+    }
+
     ///  * A method to read all of the clusters chained from a starting cluster
     ///    into a vector.
     ///
+    /// Returns an error of `InvalidData` if the chain is longer than
+    /// `VfatConfig::max_file_size` rounded up to whole clusters, which bounds
+    /// the memory a hostile image can make it allocate.
     pub fn read_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let max_clusters = match self.config.max_file_size {
+            Some(max) => self.clusters_for(max),
+            None => u32::max_value(),
+        };
+        let (read, complete) = self.read_chain_up_to(start, max_clusters, buf)?;
+        if !complete {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Cluster chain exceeds the configured maximum file size.",
+            ));
+        }
+        Ok(read)
+    }
+
+    /// Reads the chain of the directory starting at `start` into `buf`, like
+    /// `read_chain` but bounded by `VfatConfig::max_dir_entries` instead of
+    /// the maximum file size. Reading stops once one slot more than allowed
+    /// has been read, so a directory holding too many entries is still
+    /// caught by counting them.
+    pub(crate) fn read_dir_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let max_bytes = (self.config.max_dir_entries as u64).saturating_add(1).saturating_mul(32);
+        let max_clusters = self.clusters_for(max_bytes);
+        self.read_chain_up_to(start, max_clusters, buf).map(|(read, _)| read)
+    }
+
+    /// Returns the number of clusters `bytes` bytes take up, saturating.
+    fn clusters_for(&self, bytes: u64) -> u32 {
+        let cluster_size = self.cluster_size() as u64;
+        min((bytes + cluster_size - 1) / cluster_size, u32::max_value() as u64) as u32
+    }
+
+    /// Returns the raw contents of the root directory: every 32-byte slot of
+    /// its cluster chain, including LFN and deleted slots and whatever
+    /// follows the end-of-directory marker. The result can be fed to
+    /// `DirEntryParser::from_bytes`.
+    pub fn read_root_raw(&mut self) -> io::Result<Vec<u8>> {
+        let root_dir_cluster = self.root_dir_cluster;
+        self.read_dir_raw(root_dir_cluster)
+    }
+
+    /// Like `read_root_raw`, but for the directory starting at
+    /// `first_cluster`.
+    ///
+    /// Like listing a directory, reads no more of the chain than
+    /// `VfatConfig::max_dir_entries` calls for.
+    pub fn read_dir_raw(&mut self, first_cluster: Cluster) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.read_dir_chain(first_cluster, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Like `read_chain`, but stops after `max_clusters` clusters. Reading
+    /// fewer clusters than the chain holds is not an error: the number of
+    /// bytes read is returned as usual. Only the clusters read are checked
+    /// for corruption.
+    pub fn read_chain_limited(
+        &mut self,
+        start: Cluster,
+        max_clusters: u32,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<usize> {
+        self.read_chain_up_to(start, max_clusters, buf).map(|(read, _)| read)
+    }
+
+    /// Reads at most `max_clusters` clusters of the chain starting at
+    /// `start` into `buf`, returning the number of bytes read and whether
+    /// the whole chain was.
+    fn read_chain_up_to(
+        &mut self,
+        start: Cluster,
+        max_clusters: u32,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<(usize, bool)> {
         let mut cluster = Some(start);
         let mut index = 0;
-        while cluster.is_some() {
+        let mut seen = HashSet::new();
+        while cluster.is_some() && (seen.len() as u32) < max_clusters {
+            // A cluster belongs to at most one chain, and at most once.
+            if !seen.insert(cluster.unwrap()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Cluster chain is cross-linked or cyclic.",
+                ));
+            }
             let next = match self.fat_entry(cluster.unwrap())?.status() {
                 Status::Data(n) => Some(n),
                 Status::Eoc(_) => None,
@@ -121,7 +453,863 @@ impl VFat {
             index += self.read_cluster(cluster.unwrap(), 0, &mut buf[index..])?;
             cluster = next;
         }
-        Ok(index)
+        Ok((index, cluster.is_none()))
+    }
+
+    /// Checks the cluster chain of the root directory: every cluster in it
+    /// must be a data cluster of this volume, appear once, and have a FAT
+    /// entry of `Data` or `Eoc`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the chain is corrupt.
+    pub fn validate_root(&mut self) -> io::Result<()> {
+        let mut next = Some(self.root_dir_cluster);
+        let mut seen = HashSet::new();
+        while let Some(cluster) = next {
+            if cluster.inner() < 2 || cluster.inner() > self.total_clusters + 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Root directory chain leaves the data region.",
+                ));
+            }
+            if !seen.insert(cluster) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Root directory chain is cyclic.",
+                ));
+            }
+            next = match self.fat_entry(cluster)?.status() {
+                Status::Data(n) => Some(n),
+                Status::Eoc(_) => None,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Root directory chain holds a free, reserved or bad cluster.",
+                    ))
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Reads the clusters of the directory at `root` and of every directory
+    /// beneath it into the sector cache, so that listing them later doesn't
+    /// touch the device. File data is not read. Warming stops early, without
+    /// error, once the cache has no room for another cluster.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `root` is not a directory or if a directory can't
+    /// be read. Returns an error of `InvalidData` if directories are nested
+    /// more than `VfatConfig::max_depth` levels below `root`.
+    pub fn warm_cache_for_tree(&mut self, root: &Path) -> io::Result<()> {
+        let sectors_per_cluster = self.sectors_per_cluster as usize;
+        let mut pending = vec![(self.dir_cluster_of(root)?, 0)];
+        // Guards against directories that (through corruption) contain
+        // themselves or an ancestor.
+        let mut seen = HashSet::new();
+        while let Some((dir, depth)) = pending.pop() {
+            if !seen.insert(dir) {
+                continue;
+            }
+            if depth >= self.config.max_depth {
+                return Err(too_deep());
+            }
+            let clusters = self.chain(dir).collect::<io::Result<Vec<_>>>()?;
+            let mut buf = vec![0u8; clusters.len() * self.cluster_size()];
+            for (i, cluster) in clusters.into_iter().enumerate() {
+                if !self.device.has_room_for(sectors_per_cluster) {
+                    return Ok(());
+                }
+                let offset = i * self.cluster_size();
+                self.read_cluster(cluster, 0, &mut buf[offset..])?;
+            }
+            for entry in DirEntryParser::from_bytes_with_codec(&buf, self.config.oem_codec.clone()) {
+                if entry.metadata.attributes.directory() && entry.name != "." &&
+                    entry.name != ".." && entry.first_cluster.inner() >= 2
+                {
+                    pending.push((entry.first_cluster, depth + 1));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the path of the file or directory whose cluster chain contains
+    /// `cluster`, or `None` if no entry reachable from the root owns it (e.g.
+    /// because it is free or lost).
+    ///
+    /// This lists every directory and follows every chain on the volume until
+    /// the owner is found, so it costs time proportional to the size of the
+    /// whole tree. A chain that turns out to be corrupt is only searched up to
+    /// the corruption.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a directory can't be read. Returns an error of
+    /// `InvalidData` if directories are nested more than
+    /// `VfatConfig::max_depth` levels deep.
+    pub fn find_entry_for_cluster(&mut self, cluster: Cluster) -> io::Result<Option<PathBuf>> {
+        self.search_tree(|vfat, first| vfat.chain_contains(first, cluster))
+    }
+
+    /// Returns the path of the file or directory whose first cluster is
+    /// `first_cluster`, turning a `File` or `Dir` back into a path, or `None`
+    /// if no entry reachable from the root starts there.
+    ///
+    /// This lists every directory until the entry is found, so it costs time
+    /// proportional to the size of the whole tree. If several entries start at
+    /// `first_cluster`, which only happens when chains are cross-linked, the
+    /// first one found is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a directory can't be read. Returns an error of
+    /// `InvalidData` if directories are nested more than
+    /// `VfatConfig::max_depth` levels deep.
+    pub fn path_of(&mut self, first_cluster: Cluster) -> io::Result<Option<PathBuf>> {
+        self.search_tree(|_, first| first == first_cluster)
+    }
+
+    /// Returns the path of the first entry reachable from the root, the root
+    /// itself included, for whose first cluster `matches` returns `true`.
+    fn search_tree<F>(&mut self, mut matches: F) -> io::Result<Option<PathBuf>>
+    where
+        F: FnMut(&mut VFat, Cluster) -> bool,
+    {
+        let root = self.root_dir_cluster;
+        if matches(self, root) {
+            return Ok(Some(PathBuf::from("/")));
+        }
+        let mut pending = vec![(PathBuf::from("/"), root, 0)];
+        // Guards against directories that (through corruption) contain
+        // themselves or an ancestor.
+        let mut seen = HashSet::new();
+        while let Some((dir_path, dir, depth)) = pending.pop() {
+            if !seen.insert(dir) {
+                continue;
+            }
+            if depth >= self.config.max_depth {
+                return Err(too_deep());
+            }
+            let mut buf = Vec::new();
+            self.read_dir_chain(dir, &mut buf)?;
+            for entry in DirEntryParser::from_bytes_with_codec(&buf, self.config.oem_codec.clone()) {
+                let attributes = entry.metadata.attributes;
+                if entry.name == "." || entry.name == ".." || attributes.volume_id() ||
+                    entry.first_cluster.inner() < 2
+                {
+                    continue;
+                }
+                let path = dir_path.join(&entry.name);
+                if matches(self, entry.first_cluster) {
+                    return Ok(Some(path));
+                }
+                if attributes.directory() {
+                    pending.push((path, entry.first_cluster, depth + 1));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns `true` if the chain starting at `start` contains `cluster`
+    /// before it ends or turns out to be corrupt.
+    fn chain_contains(&mut self, start: Cluster, cluster: Cluster) -> bool {
+        self.chain(start)
+            .take_while(|link| link.is_ok())
+            .any(|link| link.ok() == Some(cluster))
+    }
+
+    /// Returns the first cluster of the directory at the absolute path `path`.
+    fn dir_cluster_of(&mut self, path: &Path) -> io::Result<Cluster> {
+        let mut components = path.components();
+        if components.next() != Some(Component::RootDir) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "File path should start from root.",
+            ));
+        }
+        let mut cluster = self.root_dir_cluster;
+        for component in components {
+            let name = match component {
+                Component::Normal(name) => name.to_str().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "File name contains non unicode charaters.",
+                    )
+                })?,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Canonicalized path is expected.",
+                    ))
+                }
+            };
+            let mut buf = Vec::new();
+            self.read_dir_chain(cluster, &mut buf)?;
+            let entry = DirEntryParser::from_bytes_with_codec(&buf, self.config.oem_codec.clone())
+                .find(|entry| entry.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File is not found."))?;
+            if !entry.metadata.attributes.directory() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "A Component of Path is not a directory.",
+                ));
+            }
+            cluster = entry.first_cluster;
+        }
+        Ok(cluster)
+    }
+
+    /// Returns an iterator over the clusters of the chain starting at `start`.
+    pub fn chain<'a>(&'a mut self, start: Cluster) -> Chain<'a> {
+        Chain {
+            vfat: self,
+            next: Some(start),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Returns the number of clusters in the chain starting at `start`. A
+    /// `start` below 2 (as recorded for empty files) is an empty chain.
+    pub fn chain_length(&mut self, start: Cluster) -> io::Result<u32> {
+        if start.inner() < 2 {
+            return Ok(0);
+        }
+        let mut length = 0;
+        for cluster in self.chain(start) {
+            cluster?;
+            length += 1;
+        }
+        Ok(length)
+    }
+
+    /// Returns every cluster whose FAT entry is not `Free`, in ascending order.
+    ///
+    /// The FAT is scanned once, when this method is called: the iterator is a
+    /// snapshot and does not reflect later changes to the FAT.
+    pub fn allocated_clusters(&mut self) -> io::Result<impl Iterator<Item = Cluster>> {
+        let mut clusters = Vec::new();
+        self.scan_fat(|cluster, status| {
+            if status != Status::Free {
+                clusters.push(cluster);
+            }
+        })?;
+        Ok(clusters.into_iter())
+    }
+
+    /// The size of the whole volume in bytes, as recorded in the BPB: the
+    /// reserved sectors and the FATs included. See `data_size_bytes` for the
+    /// space available to files.
+    pub fn volume_size_bytes(&self) -> u64 {
+        self.total_sectors * self.bytes_per_sector as u64
+    }
+
+    /// The size of the data region in bytes, counting whole clusters only:
+    /// the most that files and directories can occupy.
+    pub fn data_size_bytes(&self) -> u64 {
+        self.total_clusters as u64 * self.cluster_size() as u64
+    }
+
+    /// The number of bytes in free clusters. The whole FAT is read; FSInfo's
+    /// free count, which may be stale, is not consulted.
+    pub fn free_bytes(&mut self) -> io::Result<u64> {
+        let mut free = 0u64;
+        self.scan_fat(|_, status| {
+            if status == Status::Free {
+                free += 1;
+            }
+        })?;
+        Ok(free * self.cluster_size() as u64)
+    }
+
+    /// The number of bytes in clusters that are not free, i.e.
+    /// `data_size_bytes() - free_bytes()`. Bad and reserved clusters count as
+    /// used.
+    pub fn used_bytes(&mut self) -> io::Result<u64> {
+        Ok(self.data_size_bytes() - self.free_bytes()?)
+    }
+
+    /// Returns the runs of consecutive free clusters as `(first cluster,
+    /// length)` pairs, in ascending cluster order.
+    ///
+    /// The FAT is scanned once, when this method is called: the list is a
+    /// snapshot that any later allocation invalidates.
+    pub fn free_extents(&mut self) -> io::Result<Vec<(Cluster, u32)>> {
+        let mut extents: Vec<(Cluster, u32)> = Vec::new();
+        self.scan_fat(|cluster, status| {
+            if status != Status::Free {
+                return;
+            }
+            if let Some(last) = extents.last_mut() {
+                if last.0.inner() + last.1 == cluster.inner() {
+                    last.1 += 1;
+                    return;
+                }
+            }
+            extents.push((cluster, 1));
+        })?;
+        Ok(extents)
+    }
+
+    /// Whether the volume was not cleanly unmounted, according to the clean
+    /// shutdown bit of FAT entry 1. A dirty volume may need to be checked.
+    pub fn is_dirty(&mut self) -> io::Result<bool> {
+        Ok(self.fat_entry(Cluster::from(1))?.0 & CLEAN_SHUTDOWN == 0)
+    }
+
+    /// Returns the volume label recorded in the root directory, with trailing
+    /// padding removed, or `None` if the volume has no label.
+    pub fn volume_label(&mut self) -> io::Result<Option<String>> {
+        let slot = match self.find_root_slot(is_volume_label)? {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        let (sector, offset) = slot;
+        let raw = &self.device.get(sector)?[offset..offset + 11];
+        let len = raw.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+        let codec = &self.config.oem_codec;
+        Ok(Some(raw[..len].iter().map(|&c| codec.decode(c)).collect()))
+    }
+
+    /// Sets the volume label to `label`, converted to uppercase, in both the
+    /// root directory and the boot sector (and its backup, if any). An empty
+    /// `label` removes the label.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `label` is longer than 11
+    /// characters of `VfatConfig::oem_codec`, starts with a space or contains
+    /// a character not allowed in short names. Returns an error of `Other` if
+    /// the root directory has no room for a new label.
+    #[cfg(feature = "write")]
+    pub fn set_volume_label(&mut self, label: &str) -> io::Result<()> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Volume label is invalid.");
+        let encoded = label.to_uppercase()
+            .chars()
+            .map(|c| self.config.oem_codec.encode(c))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(&invalid)?;
+        if encoded.len() > 11 || encoded.first() == Some(&b' ') ||
+            !encoded.iter().all(|&c| {
+                c >= 0x80 || c.is_ascii_alphanumeric() || b" !#$%&'()-@^_`{}~".contains(&c)
+            })
+        {
+            return Err(invalid());
+        }
+        let mut raw = [b' '; 11];
+        raw[..encoded.len()].copy_from_slice(&encoded);
+        // 0xE5 would mark the entry deleted; 0x05 stands for it.
+        if raw[0] == 0xE5 {
+            raw[0] = 0x05;
+        }
+
+        let existing = self.find_root_slot(is_volume_label)?;
+        if encoded.is_empty() {
+            if let Some((sector, offset)) = existing {
+                self.sector_mut(sector)?[offset] = 0xE5;
+            }
+            raw.copy_from_slice(b"NO NAME    ");
+        } else {
+            let mut entry = [0u8; 32];
+            entry[..11].copy_from_slice(&raw);
+            entry[11] = 0x08;
+            match existing {
+                Some((sector, offset)) => {
+                    self.sector_mut(sector)?[offset..offset + 32].copy_from_slice(&entry);
+                }
+                // Keeps the end-of-directory marker after the new entry.
+                None => {
+                    let root = self.root_dir_cluster;
+                    self.add_dir_entries(root, &[entry])?;
+                }
+            }
+        }
+
+        for sector in self.boot_sectors.clone() {
+            let boot = self.sector_mut(sector)?;
+            if boot[510..512] == [0x55, 0xAA] {
+                boot[71..82].copy_from_slice(&raw);
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocates a chain of `count` free clusters and returns its first
+    /// cluster. The chain ends with an end-of-chain marker, and the FSInfo
+    /// free count and next-free hint are updated to match.
+    ///
+    /// The lowest-numbered run of at least `count` free clusters is used. If
+    /// there is none and `VfatConfig::fragmented_fallback` is set, the
+    /// lowest-numbered free clusters are chained together instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `count` is 0. Returns an error of
+    /// `Other` if there is no long enough run (and fallback is off) or not
+    /// enough free clusters at all.
+    #[cfg(feature = "write")]
+    pub fn alloc_contiguous(&mut self, count: u32) -> io::Result<Cluster> {
+        if count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot allocate zero clusters.",
+            ));
+        }
+        let extents = self.free_extents()?;
+        let clusters: Vec<Cluster> = match extents.iter().find(|&&(_, length)| length >= count) {
+            Some(&(first, _)) => (first.inner()..first.inner() + count).map(Cluster::from).collect(),
+            None if self.config.fragmented_fallback => {
+                extents
+                    .iter()
+                    .flat_map(|&(first, length)| first.inner()..first.inner() + length)
+                    .take(count as usize)
+                    .map(Cluster::from)
+                    .collect()
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "No run of free clusters is long enough.",
+                ))
+            }
+        };
+        if (clusters.len() as u32) < count {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Not enough free clusters.",
+            ));
+        }
+        self.link_chain(&clusters)?;
+        self.record_allocation(&clusters)?;
+        Ok(clusters[0])
+    }
+
+    /// Allocates `count` free clusters, adjacent or not, chains them together
+    /// and returns them in chain order. The lowest-numbered free clusters are
+    /// used. The FAT is scanned and FSInfo updated once, where `count` calls
+    /// to `alloc_contiguous(1)` would scan the whole FAT `count` times.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `count` is 0. Returns an error of
+    /// `Other` if fewer than `count` clusters are free, in which case nothing
+    /// is allocated.
+    #[cfg(feature = "write")]
+    pub fn alloc_clusters(&mut self, count: u32) -> io::Result<Vec<Cluster>> {
+        if count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot allocate zero clusters.",
+            ));
+        }
+        let mut clusters = Vec::with_capacity(count as usize);
+        self.scan_fat(|cluster, status| {
+            if status == Status::Free && clusters.len() < count as usize {
+                clusters.push(cluster);
+            }
+        })?;
+        if (clusters.len() as u32) < count {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Not enough free clusters.",
+            ));
+        }
+        self.link_chain(&clusters)?;
+        self.record_allocation(&clusters)?;
+        Ok(clusters)
+    }
+
+    /// Writes as much of `buf` as fits into `cluster` starting at byte
+    /// `offset` of the cluster and returns the number of bytes written. Like
+    /// all changes, the data reaches the device on `sync`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `cluster` is not a data cluster
+    /// or `offset` is not within the cluster.
+    #[cfg(feature = "write")]
+    pub fn write_cluster(&mut self, cluster: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
+        if cluster.inner() < 2 || offset >= self.cluster_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Write does not start within a data cluster.",
+            ));
+        }
+        let total = min(self.cluster_size() - offset, buf.len());
+        let sector_size = self.bytes_per_sector as usize;
+        let first_sector = self.cluster_start_sector(cluster);
+        let mut written = 0;
+        while written < total {
+            let position = offset + written;
+            let sector = first_sector + (position / sector_size) as u64;
+            let offset_in_sector = position % sector_size;
+            let len = min(sector_size - offset_in_sector, total - written);
+            self.sector_mut(sector)?[offset_in_sector..offset_in_sector + len]
+                .copy_from_slice(&buf[written..written + len]);
+            written += len;
+        }
+        Ok(total)
+    }
+
+    /// Returns the chain starting at `first`, or no clusters if `first` is 0,
+    /// extended with newly allocated clusters to at least `length` clusters.
+    #[cfg(feature = "write")]
+    pub(crate) fn grow_chain(&mut self, first: Cluster, length: usize) -> io::Result<Vec<Cluster>> {
+        let mut clusters = if first.inner() < 2 {
+            Vec::new()
+        } else {
+            self.chain(first).collect::<io::Result<Vec<_>>>()?
+        };
+        if clusters.len() < length {
+            let grown = self.alloc_clusters((length - clusters.len()) as u32)?;
+            if let Some(&last) = clusters.last() {
+                self.set_fat_entry(last, grown[0].inner())?;
+            }
+            clusters.extend(grown);
+        }
+        Ok(clusters)
+    }
+
+    /// Records `first_cluster` and `size` in the 8.3 slot at `slot`.
+    #[cfg(feature = "write")]
+    pub(crate) fn set_entry_cluster_and_size(
+        &mut self,
+        slot: EntrySlot,
+        first_cluster: Cluster,
+        size: u32,
+    ) -> io::Result<()> {
+        let (sector, offset) = self.entry_slot_location(slot)?;
+        let raw = &mut self.sector_mut(sector)?[offset..offset + 32];
+        let cluster = first_cluster.inner();
+        raw[20..22].copy_from_slice(&[(cluster >> 16) as u8, (cluster >> 24) as u8]);
+        raw[26..28].copy_from_slice(&[cluster as u8, (cluster >> 8) as u8]);
+        raw[28..32].copy_from_slice(&[size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8]);
+        Ok(())
+    }
+
+    /// Writes any modified sectors back to the device.
+    #[cfg(feature = "write")]
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.device.sync()
+    }
+
+    /// Marks the volume as cleanly unmounted and writes all changes to the
+    /// device. The first change after mounting (or after the last `unmount`)
+    /// marks the volume dirty, so call this once done changing it. If nothing
+    /// was changed, the clean shutdown bit is left as it was found.
+    #[cfg(feature = "write")]
+    pub fn unmount(&mut self) -> io::Result<()> {
+        if self.marked_dirty {
+            self.set_clean_shutdown(true)?;
+            self.device.sync()?;
+            self.marked_dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Writes the 32-byte directory slots `entries` to consecutive free slots
+    /// of the directory starting at cluster `dir` and returns the index of
+    /// the first. The first run of deleted or unused slots long enough is
+    /// used; slots from the end-of-directory marker on count as unused. If
+    /// the run would pass the end of the directory's chain, the chain is
+    /// extended with zero-filled clusters, so the slot after the last new
+    /// entry still ends the directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `entries` is empty. Returns an
+    /// error of `Other` if the directory would hold more than
+    /// `VfatConfig::max_dir_entries` slots or no cluster can be allocated.
+    #[cfg(feature = "write")]
+    pub fn add_dir_entries(&mut self, dir: Cluster, entries: &[[u8; 32]]) -> io::Result<usize> {
+        if entries.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No directory entries to add.",
+            ));
+        }
+        let slots_per_cluster = self.cluster_size() / 32;
+        let mut clusters = self.chain(dir).collect::<io::Result<Vec<_>>>()?;
+        let total_slots = clusters.len() * slots_per_cluster;
+
+        // Find the first run of free slots that is long enough, or one that
+        // reaches the end marker and so may run on past it.
+        let (mut run_start, mut run_length) = (total_slots, 0);
+        let mut reached_end = false;
+        for index in 0..total_slots {
+            let mut first_byte = [0u8; 1];
+            let cluster = clusters[index / slots_per_cluster];
+            self.read_cluster(cluster, index % slots_per_cluster * 32, &mut first_byte)?;
+            match first_byte[0] {
+                0x00 => {
+                    if run_length == 0 {
+                        run_start = index;
+                    }
+                    reached_end = true;
+                    break;
+                }
+                0xE5 => {
+                    if run_length == 0 {
+                        run_start = index;
+                    }
+                    run_length += 1;
+                    if run_length == entries.len() {
+                        break;
+                    }
+                }
+                _ => run_length = 0,
+            }
+        }
+        if !reached_end && run_length < entries.len() {
+            run_start = total_slots;
+        }
+        let end = run_start + entries.len();
+        if end > self.config.max_dir_entries {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Directory is full.",
+            ));
+        }
+
+        if end > total_slots {
+            let count = (end - total_slots + slots_per_cluster - 1) / slots_per_cluster;
+            let grown = self.alloc_clusters(count as u32)?;
+            for &cluster in grown.iter() {
+                self.zero_cluster(cluster)?;
+            }
+            let last = clusters[clusters.len() - 1];
+            self.set_fat_entry(last, grown[0].inner())?;
+            clusters.extend(grown);
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            let (sector, offset) = self.slot_location(&clusters, run_start + i);
+            self.sector_mut(sector)?[offset..offset + 32].copy_from_slice(entry);
+        }
+        // The entries may have covered the end marker: move it after them.
+        if reached_end && end < clusters.len() * slots_per_cluster {
+            let (sector, offset) = self.slot_location(&clusters, end);
+            self.sector_mut(sector)?[offset] = 0x00;
+        }
+        Ok(run_start)
+    }
+
+    /// Returns the sector and the offset within it of slot `index` of the
+    /// directory made of `clusters`.
+    #[cfg(feature = "write")]
+    fn slot_location(&self, clusters: &[Cluster], index: usize) -> (u64, usize) {
+        let byte = index % (self.cluster_size() / 32) * 32;
+        let cluster = clusters[index / (self.cluster_size() / 32)];
+        let sector = self.cluster_start_sector(cluster) + (byte / self.bytes_per_sector as usize) as u64;
+        (sector, byte % self.bytes_per_sector as usize)
+    }
+
+    /// Fills `cluster` with zeroes.
+    #[cfg(feature = "write")]
+    pub(crate) fn zero_cluster(&mut self, cluster: Cluster) -> io::Result<()> {
+        let start = self.cluster_start_sector(cluster);
+        for sector in start..start + self.sectors_per_cluster as u64 {
+            for b in self.sector_mut(sector)?.iter_mut() {
+                *b = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the 32 bytes of the directory slot at `slot`.
+    #[cfg(feature = "write")]
+    pub(crate) fn entry_slot_bytes(&mut self, slot: EntrySlot) -> io::Result<[u8; 32]> {
+        let (sector, offset) = self.entry_slot_location(slot)?;
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&self.device.get(sector)?[offset..offset + 32]);
+        Ok(bytes)
+    }
+
+    /// Marks the 8.3 slot at `slot` and the LFN slots just before it that
+    /// belong to it as deleted.
+    #[cfg(feature = "write")]
+    pub(crate) fn delete_entry(&mut self, slot: EntrySlot) -> io::Result<()> {
+        let mut name = [0u8; 11];
+        name.copy_from_slice(&self.entry_slot_bytes(slot)?[..11]);
+        let checksum = sfn_checksum(&name);
+        let mut index = slot.index;
+        loop {
+            let (sector, offset) = self.entry_slot_location(EntrySlot { index, ..slot })?;
+            self.sector_mut(sector)?[offset] = 0xE5;
+            if index == 0 {
+                return Ok(());
+            }
+            index -= 1;
+            let bytes = self.entry_slot_bytes(EntrySlot { index, ..slot })?;
+            if bytes[0] == 0xE5 || bytes[11] != 0x0F || bytes[13] != checksum {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Marks every cluster of the chain starting at `first` free, and counts
+    /// them as free in the FSInfo sector if the volume has a valid one.
+    #[cfg(feature = "write")]
+    pub(crate) fn free_chain(&mut self, first: Cluster) -> io::Result<()> {
+        let clusters = self.chain(first).collect::<io::Result<Vec<_>>>()?;
+        for &cluster in clusters.iter() {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        let sector = match self.fsinfo_sector {
+            Some(sector) => sector,
+            None => return Ok(()),
+        };
+        let valid = {
+            let raw = self.device.get(sector)?;
+            let fsinfo = unsafe { &*(raw.as_ptr() as *const FsInfo) };
+            fsinfo.is_valid() && fsinfo.free_count != 0xFFFFFFFF
+        };
+        if valid {
+            let raw = self.sector_mut(sector)?;
+            let fsinfo = unsafe { &mut *(raw.as_mut_ptr() as *mut FsInfo) };
+            fsinfo.free_count += clusters.len() as u32;
+        }
+        Ok(())
+    }
+
+    /// Returns the sector and the offset within it of the 8.3 slot at `slot`.
+    #[cfg(feature = "write")]
+    fn entry_slot_location(&mut self, slot: EntrySlot) -> io::Result<(u64, usize)> {
+        let slots_per_cluster = self.cluster_size() / 32;
+        let cluster = match self.chain(slot.dir).nth(slot.index / slots_per_cluster) {
+            Some(cluster) => cluster?,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Directory entry lies past the end of its directory.",
+                ))
+            }
+        };
+        Ok(self.slot_location(&[cluster], slot.index % slots_per_cluster))
+    }
+
+    /// Returns sector `n`, numbered as by the device, for modification. Every
+    /// change to the volume goes through here, so the first one clears the
+    /// clean shutdown bit and writes that to the device before anything else
+    /// is changed. Until then, a volume mounted with write support is only
+    /// read, so read-only devices can be mounted.
+    #[cfg(feature = "write")]
+    fn sector_mut(&mut self, n: u64) -> io::Result<&mut [u8]> {
+        if !self.marked_dirty {
+            self.marked_dirty = true;
+            let marked = match self.set_clean_shutdown(false) {
+                Ok(()) => self.device.sync(),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = marked {
+                self.marked_dirty = false;
+                return Err(e);
+            }
+        }
+        self.device.get_mut(n)
+    }
+
+    /// Sets or clears the clean shutdown bit of FAT entry 1.
+    #[cfg(feature = "write")]
+    fn set_clean_shutdown(&mut self, clean: bool) -> io::Result<()> {
+        let entry = self.fat_entry(Cluster::from(1))?.0;
+        let entry = if clean {
+            entry | CLEAN_SHUTDOWN
+        } else {
+            entry & !CLEAN_SHUTDOWN
+        };
+        self.set_fat_entry(Cluster::from(1), entry)
+    }
+
+    /// Links `clusters` into a chain, in order, terminated by an end-of-chain
+    /// marker.
+    #[cfg(feature = "write")]
+    fn link_chain(&mut self, clusters: &[Cluster]) -> io::Result<()> {
+        for (i, &cluster) in clusters.iter().enumerate() {
+            let next = clusters.get(i + 1).map(|c| c.inner()).unwrap_or(0x0FFFFFFF);
+            self.set_fat_entry(cluster, next)?;
+        }
+        Ok(())
+    }
+
+    /// Accounts for the allocation of `clusters` in the FSInfo sector, if the
+    /// volume has a valid one.
+    #[cfg(feature = "write")]
+    fn record_allocation(&mut self, clusters: &[Cluster]) -> io::Result<()> {
+        let sector = match self.fsinfo_sector {
+            Some(sector) => sector,
+            None => return Ok(()),
+        };
+        let valid = {
+            let raw = self.device.get(sector)?;
+            unsafe { &*(raw.as_ptr() as *const FsInfo) }.is_valid()
+        };
+        if !valid || clusters.is_empty() {
+            return Ok(());
+        }
+        let raw = self.sector_mut(sector)?;
+        let fsinfo = unsafe { &mut *(raw.as_mut_ptr() as *mut FsInfo) };
+        if fsinfo.free_count != 0xFFFFFFFF {
+            fsinfo.free_count = fsinfo.free_count.saturating_sub(clusters.len() as u32);
+        }
+        // The hint wraps around to the first data cluster past the last one.
+        let next = clusters[clusters.len() - 1].inner() + 1;
+        fsinfo.next_free = if next > self.total_clusters + 1 { 2 } else { next };
+        Ok(())
+    }
+
+    /// Returns the sector and byte offset of the first root directory entry
+    /// for which `pred` holds. Entries after the end-of-directory marker are
+    /// not considered, but the marker itself is.
+    fn find_root_slot<F>(&mut self, mut pred: F) -> io::Result<Option<(u64, usize)>>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let root = self.root_dir_cluster;
+        let clusters = self.chain(root).collect::<io::Result<Vec<_>>>()?;
+        for cluster in clusters {
+            let first = self.cluster_start_sector(cluster);
+            for nsector in first..first + self.sectors_per_cluster as u64 {
+                let sector = self.device.get(nsector)?;
+                for (i, slot) in sector.chunks(32).enumerate() {
+                    if pred(slot) {
+                        return Ok(Some((nsector, i * 32)));
+                    }
+                    if slot[0] == 0x00 {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Calls `f` with every data cluster and the status of its FAT entry, in
+    /// ascending order, reading each sector of the FAT exactly once.
+    fn scan_fat<F: FnMut(Cluster, Status)>(&mut self, mut f: F) -> io::Result<()> {
+        let entries_per_sector = self.bytes_per_sector as u32 / 4;
+        let last = self.total_clusters + 1;
+        for n in 0..self.sectors_per_fat {
+            let first = n * entries_per_sector;
+            if first > last {
+                break;
+            }
+            let sector = self.device.get(self.fat_start_sector + n as u64)?;
+            for (i, raw) in sector.chunks(4).enumerate() {
+                let cluster = first + i as u32;
+                if cluster > last {
+                    break;
+                }
+                if cluster >= 2 {
+                    let entry = unsafe { &*(raw.as_ptr() as *const FatEntry) };
+                    f(cluster.into(), entry.status());
+                }
+            }
+        }
+        Ok(())
     }
 
     ///  * A method to return a reference to a `FatEntry` for a cluster where the
@@ -142,6 +1330,117 @@ impl VFat {
             &*(sector[offset_in_sector..offset_in_sector + 4].as_ptr() as *const FatEntry)
         })
     }
+
+    /// Returns the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last one.
+    pub(crate) fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(Some(next)),
+            Status::Eoc(_) => Ok(None),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "FAT entry other than Data and Eoc encountered.",
+            )),
+        }
+    }
+}
+
+/// The bit of FAT entry 1 that is set while the volume is cleanly unmounted.
+const CLEAN_SHUTDOWN: u32 = 1 << 27;
+
+/// Checks the last component of `path` with `validate_filename`.
+#[cfg(feature = "write")]
+pub(crate) fn validate_file_name_of(path: &Path) -> io::Result<()> {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => validate_filename(name),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Path does not end in a valid file name.",
+        )),
+    }
+}
+
+/// Whether the raw directory entry `slot` holds the volume label.
+fn is_volume_label(slot: &[u8]) -> bool {
+    slot[0] != 0x00 && slot[0] != 0xE5 && slot[11] & !0x20 == 0x08
+}
+
+/// Whether sector 0 of `device` looks like the EBPB of a FAT32 file system
+/// rather than a master boot record.
+fn is_unpartitioned<T: BlockDevice>(device: &mut T) -> bool {
+    match BiosParameterBlock::from(device, 0) {
+        Ok(bpb) => {
+            let bps = bpb.bytes_per_sector;
+            let spc = bpb.sectors_per_cluster;
+            bps >= 512 && bps <= 4096 && bps.is_power_of_two() && spc.is_power_of_two() &&
+                bpb.number_of_fats > 0 && bpb.number_of_reserved_sectors > 0 &&
+                bpb.sectors_per_fat > 0
+        }
+        Err(_) => false,
+    }
+}
+
+/// An iterator over the clusters of a chain, in chain order.
+///
+/// Yields an error and stops if an entry other than `Data` or `Eoc` is found,
+/// or if the chain revisits a cluster (and so is cross-linked or cyclic).
+pub struct Chain<'a> {
+    vfat: &'a mut VFat,
+    next: Option<Cluster>,
+    seen: HashSet<Cluster>,
+}
+
+impl<'a> Iterator for Chain<'a> {
+    type Item = io::Result<Cluster>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cluster = self.next.take()?;
+        if !self.seen.insert(cluster) {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Cluster chain is cross-linked or cyclic.",
+            )));
+        }
+        match self.vfat.fat_entry(cluster).map(|entry| entry.status()) {
+            Ok(Status::Data(next)) => self.next = Some(next),
+            Ok(Status::Eoc(_)) => (),
+            Ok(_) => {
+                return Some(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "FAT entry other than Data and Eoc encountered.",
+                )))
+            }
+            Err(e) => return Some(Err(e)),
+        }
+        Some(Ok(cluster))
+    }
+}
+
+#[cfg(feature = "write")]
+impl VFat {
+    /// Sets the FAT entry for `cluster` to `value` in every copy of the FAT.
+    /// The reserved upper four bits of the entry are preserved.
+    fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let offset_by_byte = cluster.inner() * 4;
+        let offset_by_sector = offset_by_byte / self.bytes_per_sector as u32;
+        if offset_by_sector >= self.sectors_per_fat {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Cluster does not exist.",
+            ));
+        }
+        let offset_in_sector = offset_by_byte as usize % self.bytes_per_sector as usize;
+        for fat in 0..self.number_of_fats as u64 {
+            let nsector = self.fat_start_sector + fat * self.sectors_per_fat as u64 +
+                offset_by_sector as u64;
+            let sector = self.sector_mut(nsector)?;
+            let entry = unsafe {
+                &mut *(sector[offset_in_sector..offset_in_sector + 4].as_mut_ptr() as *mut FatEntry)
+            };
+            entry.0 = (entry.0 & (0xF << 28)) | (value & !(0xF << 28));
+        }
+        Ok(())
+    }
 }
 
 impl<'a> FileSystem for &'a Shared<VFat> {
@@ -153,7 +1452,7 @@ impl<'a> FileSystem for &'a Shared<VFat> {
         // `canonicalize` is unavailable in the suppied std
         // let canon_path = path.as_ref().canonicalize()?;
         // let mut componenets = canon_path.as_path().components();
-        let mut components = path.as_ref().components();
+        let mut components = path.as_ref().components().peekable();
         if components.next() != Some(Component::RootDir) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -171,6 +1470,9 @@ impl<'a> FileSystem for &'a Shared<VFat> {
             if let Some(Component::Normal(path_seg)) = component {
                 match current_dir.find(path_seg)? {
                     Entry::Dir(dir) => {
+                        if components.peek().is_some() {
+                            dir.validate_traversable()?;
+                        }
                         current_dir = dir;
                     }
                     Entry::File(file) => {
@@ -194,6 +1496,7 @@ impl<'a> FileSystem for &'a Shared<VFat> {
                         "A Component of Path is not a directory.",
                     ))
                 } else {
+                    file.validate_for_open()?;
                     Ok(Entry::File(file))
                 }
             }
@@ -201,26 +1504,60 @@ impl<'a> FileSystem for &'a Shared<VFat> {
         }
     }
 
+    #[cfg(feature = "write")]
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        validate_file_name_of(path.as_ref())?;
+        create::create_file(self, path.as_ref())
+    }
+
+    #[cfg(not(feature = "write"))]
     fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+        Err(read_only_error())
     }
 
+    #[cfg(feature = "write")]
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
+    where
+        P: AsRef<Path>,
+    {
+        validate_file_name_of(path.as_ref())?;
+        create::create_dir(self, path.as_ref(), parents)
+    }
+
+    #[cfg(not(feature = "write"))]
     fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
     where
         P: AsRef<Path>,
     {
-        unimplemented!("read only file system")
+        Err(read_only_error())
+    }
+
+    #[cfg(feature = "write")]
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        validate_file_name_of(to.as_ref())?;
+        create::rename(self, from.as_ref(), to.as_ref())
     }
 
+    #[cfg(not(feature = "write"))]
     fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
     where
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        unimplemented!("read only file system")
+        Err(read_only_error())
+    }
+
+    #[cfg(feature = "write")]
+    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "remove is not supported"))
     }
 
+    #[cfg(not(feature = "write"))]
     fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+        Err(read_only_error())
     }
 }
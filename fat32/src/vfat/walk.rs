@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use traits::{self, FileSystem};
+use vfat::{Cluster, Entry, Metadata, Shared, VFat};
+use vfat::dir::EntryIter;
+
+/// An entry found by `Shared<VFat>::walk`, along with its path and the
+/// details most callers need without inspecting `entry`.
+#[derive(Debug)]
+pub struct WalkEntry {
+    /// The absolute path of the entry.
+    pub path: PathBuf,
+    pub entry: Entry,
+    pub metadata: Metadata,
+    pub is_dir: bool,
+    /// The size of a file in bytes; 0 for a directory.
+    pub size: u64,
+    /// How far below the walked directory the entry is: 1 for its direct
+    /// children.
+    pub depth: usize,
+}
+
+/// A depth-first iterator over the entries beneath a directory. Created by
+/// `Shared<VFat>::walk`.
+pub struct Walk {
+    /// The directories being listed, innermost last, with their paths.
+    stack: Vec<(PathBuf, EntryIter)>,
+    /// An error listing the directory yielded last, to be yielded next.
+    error: Option<io::Error>,
+    /// The first clusters of the directories listed so far, to catch a
+    /// corrupt entry leading back to one of them.
+    visited: HashSet<Cluster>,
+    max_depth: usize,
+}
+
+impl Shared<VFat> {
+    /// Returns an iterator over every entry beneath the directory at `path`,
+    /// recursively. A directory is yielded before its contents; `.`, `..`
+    /// and the volume label are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` can't be opened as a directory. An error
+    /// listing a subdirectory is yielded by the iterator right after the
+    /// subdirectory, in place of its contents; so is an error of
+    /// `InvalidData` for a subdirectory whose first cluster was already
+    /// walked, or which is more than `VfatConfig::max_depth` levels below
+    /// `path`.
+    pub fn walk<P: AsRef<Path>>(&self, path: P) -> io::Result<Walk> {
+        use traits::Dir;
+
+        let dir = self.open_dir(path.as_ref())?;
+        let entries = dir.entries()?;
+        let mut visited = HashSet::new();
+        visited.insert(dir.first_cluster());
+        Ok(Walk {
+            stack: vec![(path.as_ref().to_path_buf(), entries)],
+            error: None,
+            visited,
+            max_depth: self.borrow().config.max_depth,
+        })
+    }
+
+    /// Returns the paths of the files beneath the directory at `root`,
+    /// recursively, whose extension (the part of the name after the last
+    /// `.`) is `ext`, compared case-insensitively. `ext` is given without the
+    /// dot. Files without an extension and directories never match.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error `walk` returns or yields.
+    pub fn find_by_extension<P: AsRef<Path>>(&self, root: P, ext: &str) -> io::Result<Vec<PathBuf>> {
+        let mut found = Vec::new();
+        for entry in self.walk(root)? {
+            let entry = entry?;
+            let matches = entry.path.extension().map_or(false, |found_ext| {
+                found_ext.to_str().map_or(false, |found_ext| found_ext.eq_ignore_ascii_case(ext))
+            });
+            if !entry.is_dir && matches {
+                found.push(entry.path);
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl Iterator for Walk {
+    type Item = io::Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use traits::{Dir, Entry};
+
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        loop {
+            let (path, entry) = {
+                let &mut (ref dir_path, ref mut entries) = self.stack.last_mut()?;
+                match entries.next() {
+                    Some(entry) => (dir_path.join(entry.name()), entry),
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                }
+            };
+            if entry.name() == "." || entry.name() == ".." ||
+                entry.metadata().attributes.volume_id()
+            {
+                continue;
+            }
+
+            let metadata = entry.metadata().clone();
+            let size = entry.as_file().map(|file| traits::File::size(file)).unwrap_or(0);
+            let is_dir = entry.is_dir();
+            let depth = self.stack.len();
+            if let Some(dir) = entry.as_dir() {
+                let first_cluster = dir.first_cluster();
+                if first_cluster.inner() >= 2 && !self.visited.insert(first_cluster) {
+                    self.error = Some(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Directory leads back to one already walked.",
+                    ));
+                } else if depth >= self.max_depth {
+                    self.error = Some(too_deep());
+                } else {
+                    match dir.entries() {
+                        Ok(entries) => self.stack.push((path.clone(), entries)),
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            }
+            return Some(Ok(WalkEntry {
+                path,
+                entry,
+                metadata,
+                is_dir,
+                size,
+                depth,
+            }));
+        }
+    }
+}
+
+/// The error for a directory tree nested deeper than `VfatConfig::max_depth`.
+pub(crate) fn too_deep() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Directory nesting is too deep.",
+    )
+}